@@ -0,0 +1,196 @@
+use crate::{Side, Board, swap_turn};
+use super::evaluation::Tapered;
+use super::pawns::{forward, relative_rank};
+use super::piece::ChessPiece;
+
+/// Weights of the piece-activity terms. Pawns attack the square straight in front of them,
+/// so that is the only square from which an enemy pawn can chase a piece away.
+#[derive(Clone, Debug)]
+pub struct ActivityParams {
+    /// For every move to a square no enemy pawn attacks, in ChessPiece order.
+    pub mobility: [Tapered; 6],
+    pub bishop_pair: Tapered,
+    /// For a rook on a file without pawns.
+    pub rook_open_file: Tapered,
+    /// For a rook on a file with only enemy pawns.
+    pub rook_half_open_file: Tapered,
+    /// For a rook on the rank the enemy pawns start on.
+    pub rook_seventh_rank: Tapered,
+    /// For a knight on the 4th to 6th rank with a pawn of its side right behind it and no
+    /// enemy pawn in front of it on the file.
+    pub knight_outpost: Tapered,
+    /// For a piece in the enemy half left with at most `trapped_moves` moves, in ChessPiece order.
+    pub trapped: [Tapered; 6],
+    pub trapped_moves: usize
+}
+
+impl Default for ActivityParams {
+    fn default() -> ActivityParams {
+        ActivityParams {
+            mobility: [
+                Tapered::new(0, 0), Tapered::new(1, 1), Tapered::new(2, 3),
+                Tapered::new(0, 0), Tapered::new(2, 2), Tapered::new(1, 2)
+            ],
+            bishop_pair: Tapered::new(15, 25),
+            rook_open_file: Tapered::new(10, 5),
+            rook_half_open_file: Tapered::new(5, 3),
+            rook_seventh_rank: Tapered::new(10, 15),
+            knight_outpost: Tapered::new(10, 6),
+            trapped: [
+                Tapered::new(0, 0), Tapered::new(-40, -30), Tapered::new(-25, -20),
+                Tapered::new(0, 0), Tapered::new(-25, -20), Tapered::new(-30, -25)
+            ],
+            trapped_moves: 1
+        }
+    }
+}
+
+impl ActivityParams {
+    /// Nothing counts for activity, a piece is still trapped with at most one move.
+    #[cfg(test)]
+    pub fn zero() -> ActivityParams {
+        ActivityParams {
+            mobility: [Tapered::default(); 6],
+            bishop_pair: Tapered::default(),
+            rook_open_file: Tapered::default(),
+            rook_half_open_file: Tapered::default(),
+            rook_seventh_rank: Tapered::default(),
+            knight_outpost: Tapered::default(),
+            trapped: [Tapered::default(); 6],
+            trapped_moves: 1
+        }
+    }
+}
+
+//An enemy pawn stands right in front of the square, as seen from the side's pieces
+fn attacked_by_pawn(board: &Board, (x, y): (i8, i8), side: Side) -> bool {
+    board.piece_on((x, y + forward(side))) == Some((ChessPiece::Pawn, swap_turn(side)))
+}
+
+fn pawn_on_file(pieces: &[(ChessPiece, Side, (i8, i8))], file: i8, side: Side) -> bool {
+    pieces.iter().any(|(piece, pawn_side, (x, _))| *piece == ChessPiece::Pawn && *pawn_side == side && *x == file)
+}
+
+fn rook_placement(pieces: &[(ChessPiece, Side, (i8, i8))], (x, y): (i8, i8), side: Side, params: &ActivityParams) -> Tapered {
+    let mut score = Tapered::default();
+    if !pawn_on_file(pieces, x, side) {
+        score += if pawn_on_file(pieces, x, swap_turn(side)) {params.rook_half_open_file} else {params.rook_open_file};
+    }
+    if relative_rank(side, y) == 6 {score += params.rook_seventh_rank;}
+    score
+}
+
+fn is_outpost(board: &Board, (x, y): (i8, i8), side: Side) -> bool {
+    let step = forward(side);
+    (3..=5).contains(&relative_rank(side, y))
+        && board.piece_on((x, y - step)) == Some((ChessPiece::Pawn, side))
+        && (1..8).all(|distance| board.piece_on((x, y + step * distance)) != Some((ChessPiece::Pawn, swap_turn(side))))
+}
+
+/// Mobility and placement of the pieces besides kings and pawns, white minus black.
+pub fn piece_activity(board: &Board, pieces: &[(ChessPiece, Side, (i8, i8))], params: &ActivityParams) -> Tapered {
+    let mut score = Tapered::default();
+    for side in [Side::White, Side::Black] {
+        let mut side_score = Tapered::default();
+        let bishops = pieces.iter().filter(|(piece, piece_side, _)| *piece == ChessPiece::Bishop && *piece_side == side).count();
+        if bishops >= 2 {side_score += params.bishop_pair;}
+
+        let side_pieces = pieces.iter()
+            .filter(|(piece, piece_side, _)| *piece_side == side && !matches!(piece, ChessPiece::King | ChessPiece::Pawn));
+        for (piece, _, square) in side_pieces {
+            let moves = ChessPiece::get_pseudolegal_moves(board.get_board_state(), *square, piece, &side).into_iter()
+                .filter(|to| !attacked_by_pawn(board, *to, side))
+                .count();
+            side_score += params.mobility[*piece as usize] * moves as i32;
+            if moves <= params.trapped_moves && relative_rank(side, square.1) >= 4 {
+                side_score += params.trapped[*piece as usize];
+            }
+
+            match piece {
+                ChessPiece::Rook => side_score += rook_placement(pieces, *square, side, params),
+                ChessPiece::Knight if is_outpost(board, *square, side) => side_score += params.knight_outpost,
+                _ => {}
+            }
+        }
+        score += side_score * side as i32;
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Kings in the corners, out of the way of the pieces under test
+    const WHITE_KING: (i8, i8) = (0, 7);
+    const BLACK_KING: (i8, i8) = (7, 0);
+
+    fn activity(board: &Board, params: &ActivityParams) -> Tapered {
+        piece_activity(board, &board.pieces(), params)
+    }
+
+    #[test]
+    fn mobility_skips_squares_enemy_pawns_attack() {
+        let params = ActivityParams { mobility: ActivityParams::default().mobility, ..ActivityParams::zero() };
+        let knight = (ChessPiece::Knight, Side::White, (3, 4));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[knight]), &params), Tapered::new(16, 16));
+
+        //The black pawn on (4, 1) guards (4, 2), one of the knight's eight squares
+        let pawn = (ChessPiece::Pawn, Side::Black, (4, 1));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[knight, pawn]), &params), Tapered::new(14, 14));
+    }
+
+    #[test]
+    fn knight_outpost_needs_a_pawn_behind_it() {
+        let params = ActivityParams { knight_outpost: Tapered::new(10, 6), ..ActivityParams::zero() };
+        let knight = (ChessPiece::Knight, Side::White, (3, 3));
+        let protector = (ChessPiece::Pawn, Side::White, (3, 4));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[knight, protector]), &params), Tapered::new(10, 6));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[knight]), &params), Tapered::default());
+
+        //An enemy pawn further up the file can still chase it away
+        let chaser = (ChessPiece::Pawn, Side::Black, (3, 1));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[knight, protector, chaser]), &params), Tapered::default());
+
+        //Too far back to be an outpost
+        let home_knight = (ChessPiece::Knight, Side::White, (3, 5));
+        let home_protector = (ChessPiece::Pawn, Side::White, (3, 6));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[home_knight, home_protector]), &params), Tapered::default());
+    }
+
+    #[test]
+    fn boxed_in_bishop_in_the_enemy_half_is_trapped() {
+        let params = ActivityParams { trapped: ActivityParams::default().trapped, ..ActivityParams::zero() };
+        //Its own pawns take both diagonal squares of the bishop on the edge
+        let walls = [(ChessPiece::Pawn, Side::White, (1, 1)), (ChessPiece::Pawn, Side::White, (1, 3))];
+        let bishop = (ChessPiece::Bishop, Side::White, (0, 2));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[bishop, walls[0], walls[1]]), &params), params.trapped[ChessPiece::Bishop as usize]);
+
+        //The same bishop with a way out
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[bishop, walls[0]]), &params), Tapered::default());
+
+        //Boxed in at home it is only passive
+        let home_walls = [(ChessPiece::Pawn, Side::White, (1, 4)), (ChessPiece::Pawn, Side::White, (1, 6))];
+        let home_bishop = (ChessPiece::Bishop, Side::White, (0, 5));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[home_bishop, home_walls[0], home_walls[1]]), &params), Tapered::default());
+    }
+
+    #[test]
+    fn rooks_like_open_files_and_the_seventh_rank() {
+        let params = ActivityParams {
+            rook_open_file: Tapered::new(10, 5),
+            rook_half_open_file: Tapered::new(5, 3),
+            rook_seventh_rank: Tapered::new(10, 15),
+            ..ActivityParams::zero()
+        };
+        let rook = (ChessPiece::Rook, Side::White, (2, 4));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[rook]), &params), Tapered::new(10, 5));
+        let black_pawn = (ChessPiece::Pawn, Side::Black, (2, 1));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[rook, black_pawn]), &params), Tapered::new(5, 3));
+        let white_pawn = (ChessPiece::Pawn, Side::White, (2, 6));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[rook, black_pawn, white_pawn]), &params), Tapered::default());
+
+        let seventh_rank_rook = (ChessPiece::Rook, Side::White, (2, 1));
+        assert_eq!(activity(&Board::with_kings(WHITE_KING, BLACK_KING, &[seventh_rank_rook, white_pawn]), &params), Tapered::new(10, 15));
+    }
+}
@@ -2,9 +2,23 @@ use std::{rc::Rc, borrow::BorrowMut};
 
 use macroquad::prelude::*;
 use crate::chess::piece::{ChessPiece, Side};
+use crate::chess::zobrist;
+use crate::chess::r#move::RAY_INCREMENTS;
 
 pub const CELL_SIZE: f32 = 80.0;
 const GRID_SIZE: u8 = 8;
+const KNIGHT_OFFSETS: [(i8, i8); 8] = [(1, -2), (2, -1), (2, 1), (1, 2), (-1, 2), (-2, 1), (-2, -1), (-1, -2)];
+//Both sides' back rank at the start, from x = 0
+const BACK_RANK: [ChessPiece; 8] = [
+    ChessPiece::Rook,
+    ChessPiece::Knight,
+    ChessPiece::Bishop,
+    ChessPiece::King,
+    ChessPiece::Queen,
+    ChessPiece::Bishop,
+    ChessPiece::Knight,
+    ChessPiece::Rook,
+];
 
 const CELL_COLORS: (Color, Color) = (Color::new(0.44314, 0.55294, 0.32941, 1.0), 
                                      Color::new(0.92549, 0.92549, 0.83529, 1.0));
@@ -24,7 +38,7 @@ pub struct Rectangle {
     max_y: f32
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Move {
     pub from: (i8, i8),
     pub to: (i8, i8),
@@ -142,9 +156,8 @@ pub struct BoardPiece {
 
 #[derive(Debug)]
 struct BoardEvaluationInfo {
-    pieces: Vec<ChessPiece>,
-    white_pieces: u8,
-    black_pieces: u8
+    white_pieces: Vec<ChessPiece>,
+    black_pieces: Vec<ChessPiece>
 }
 
 #[derive(Clone)]
@@ -153,22 +166,23 @@ pub struct Board {
     //TODO Should probably move to BoardView
     pub text_params: TextParams,
     pub text_spacing: f32,
-    pub last_move: Move
+    pub last_move: Move,
+    //Pieces taken by make_move, restored by unmake_move
+    captured_pieces: Vec<Option<(ChessPiece, Side)>>,
+    //Zobrist key of the piece placement, side to move is left to the caller
+    hash: u64,
+    //Zobrist key of the pawns alone, keys the pawn hash table
+    pawn_hash: u64,
+    //Key before every move played or made along with reversible_plies at that point
+    history: Vec<(u64, u16)>,
+    //Plies since the last capture or pawn move, no position before it can come back
+    reversible_plies: u16
 }
 
 
 impl Board {
     fn add_pieces(&mut self) {
-        let main_pieces_order: [ChessPiece; 8] = [
-            ChessPiece::Rook,
-            ChessPiece::Knight,
-            ChessPiece::Bishop,
-            ChessPiece::King,
-            ChessPiece::Queen,
-            ChessPiece::Bishop,
-            ChessPiece::Knight,
-            ChessPiece::Rook,
-        ];
+        let main_pieces_order: [ChessPiece; 8] = BACK_RANK;
         let pawn_rows: [(Side, usize); 2] = [
             (Side::Black, 1),
             (Side::White, 6)
@@ -208,27 +222,19 @@ impl Board {
     }
 
     fn get_board_evaluation_info(board: &Vec<Vec<Cell>>) -> BoardEvaluationInfo {
-        let mut pieces: Vec<ChessPiece> = vec![];
-        let mut white_pieces: u8 = 0;
-        let mut black_pieces: u8 = 0;
-        
-        for cell in board.iter().flatten() {
-            if let CellState::Piece(piece) = cell.state {
-                pieces.push(piece);
-            }
+        let mut white_pieces: Vec<ChessPiece> = vec![];
+        let mut black_pieces: Vec<ChessPiece> = vec![];
 
-            if let Some(side) = cell.side {
-                match side {
-                    Side::White => white_pieces += 1,
-                    Side::Black => black_pieces += 1
-                }
+        for (piece, side) in board.iter().flatten().filter_map(Cell::get_piece_side) {
+            match side {
+                Side::White => white_pieces.push(piece),
+                Side::Black => black_pieces.push(piece)
             }
         }
 
         BoardEvaluationInfo {
-            pieces,
-            black_pieces,
-            white_pieces
+            white_pieces,
+            black_pieces
         }
     }
 
@@ -286,11 +292,23 @@ impl Board {
         moves_for_boardpiece
     }
 
+    //Pseudolegal moves that do not leave the own king attacked
+    pub fn get_legal_moves_for_side(&mut self, side: Side) -> Vec<Move> {
+        let mut legal_moves: Vec<Move> = vec![];
+        for possible_move in self.get_all_moves_for_side(side) {
+            self.make_move(&possible_move);
+            if !self.is_in_check(side) {legal_moves.push(possible_move.clone());}
+            self.unmake_move(&possible_move);
+        }
+        legal_moves
+    }
+
+    //White's material minus black's, from whose_turn's point of view
     pub fn evaluate_board_score(board: &Vec<Vec<Cell>>, whose_turn: Side) -> i32 {
         let board_eval_info = Self::get_board_evaluation_info(board);
-        let material_weight = Self::evaluate_material_weight(board_eval_info.pieces);
-        let score = material_weight * (board_eval_info.white_pieces as i32 - board_eval_info.black_pieces as i32) * whose_turn as i32;
-        return score;
+        let white_material = Self::evaluate_material_weight(board_eval_info.white_pieces);
+        let black_material = Self::evaluate_material_weight(board_eval_info.black_pieces);
+        (white_material - black_material) * whose_turn as i32
     }
 
     pub async fn new(screen_width: f32, screen_height: f32) -> Board {
@@ -323,12 +341,110 @@ impl Board {
             board,
             text_params,
             text_spacing: ChessPiece::get_center_offset(&ChessPiece::Pawn, &text_params),
-            last_move: Move { from: (0, 0), to: (0, 0) }
+            last_move: Move { from: (0, 0), to: (0, 0) },
+            captured_pieces: vec![],
+            hash: 0,
+            pawn_hash: 0,
+            history: vec![],
+            reversible_plies: 0
         };
         board.add_pieces();
+        board.hash = board.compute_hash();
+        board.pawn_hash = board.compute_pawn_hash();
         return board;
     }
 
+    /// Board without pieces or screen layout, for tools that only need the rules.
+    pub fn empty() -> Board {
+        let board = (0..GRID_SIZE)
+            .map(|_| (0..GRID_SIZE).map(|_| Cell::new((0.0, 0.0), &CELL_COLORS.0)).collect())
+            .collect();
+        Board {
+            board,
+            text_params: TextParams::default(),
+            text_spacing: 0.0,
+            last_move: Move { from: (0, 0), to: (0, 0) },
+            captured_pieces: vec![],
+            hash: 0,
+            pawn_hash: 0,
+            history: vec![],
+            reversible_plies: 0
+        }
+    }
+
+    /// Empty board with just the given pieces on it.
+    #[cfg(test)]
+    pub fn from_pieces(pieces: &[(ChessPiece, Side, (i8, i8))]) -> Board {
+        let mut board = Board::empty();
+        for (piece, side, square) in pieces {
+            board.set_piece(*square, Some((*piece, *side)));
+        }
+        board
+    }
+
+    /// Both kings on the given squares plus the given pieces.
+    #[cfg(test)]
+    pub fn with_kings(white_king: (i8, i8), black_king: (i8, i8), pieces: &[(ChessPiece, Side, (i8, i8))]) -> Board {
+        let mut board = Board::from_pieces(pieces);
+        board.set_piece(white_king, Some((ChessPiece::King, Side::White)));
+        board.set_piece(black_king, Some((ChessPiece::King, Side::Black)));
+        board
+    }
+
+    /// Every piece of the start position.
+    #[cfg(test)]
+    pub fn start_pieces() -> Vec<(ChessPiece, Side, (i8, i8))> {
+        let mut pieces = vec![];
+        for (x, piece) in BACK_RANK.into_iter().enumerate() {
+            let x = x as i8;
+            pieces.push((piece, Side::Black, (x, 0)));
+            pieces.push((ChessPiece::Pawn, Side::Black, (x, 1)));
+            pieces.push((ChessPiece::Pawn, Side::White, (x, 6)));
+            pieces.push((piece, Side::White, (x, 7)));
+        }
+        pieces
+    }
+
+    /// Start position without the screen layout, hashed like the one a game starts from.
+    #[cfg(test)]
+    pub fn start_position() -> Board {
+        Board::from_pieces(&Board::start_pieces())
+    }
+
+    /// Puts a piece on the square or clears it, keeping the hash up to date.
+    pub fn set_piece(&mut self, square: (i8, i8), piece: Option<(ChessPiece, Side)>) {
+        if let Some((old_piece, old_side)) = self.piece_on(square) {
+            self.toggle_piece_hash(&old_piece, old_side, square);
+        }
+        let cell = &mut self.board[square.1 as usize][square.0 as usize];
+        match piece {
+            Some((piece, side)) => {
+                cell.modify_cell(CellState::Piece(piece), Some(side));
+                self.toggle_piece_hash(&piece, side, square);
+            },
+            None => cell.modify_cell(CellState::Empty, None)
+        }
+    }
+
+    pub fn pieces(&self) -> Vec<(ChessPiece, Side, (i8, i8))> {
+        let mut pieces = vec![];
+        for y in 0..GRID_SIZE as i8 {
+            for x in 0..GRID_SIZE as i8 {
+                if let Some((piece, side)) = self.piece_on((x, y)) {
+                    pieces.push((piece, side, (x, y)));
+                }
+            }
+        }
+        pieces
+    }
+
+    pub fn piece_count(&self) -> usize {
+        self.board.iter()
+            .flatten()
+            .filter(|cell| matches!(cell.state, CellState::Piece(_)))
+            .count()
+    }
+
     pub fn get_piece(board: &Vec<Vec<Cell>>, cell: (i8, i8)) -> Option<ChessPiece> {
         let cell = &board[cell.1 as usize][cell.0 as usize];
 
@@ -348,6 +464,76 @@ impl Board {
         &self.board
     }
 
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn pawn_hash(&self) -> u64 {
+        self.pawn_hash
+    }
+
+    fn compute_hash(&self) -> u64 {
+        let mut hash: u64 = 0;
+        for side in [Side::White, Side::Black] {
+            for board_piece in self.get_sides_boardpieces(side) {
+                hash ^= zobrist::piece_key(&board_piece.piece, side, board_piece.location);
+            }
+        }
+        hash
+    }
+
+    fn compute_pawn_hash(&self) -> u64 {
+        self.pieces().iter()
+            .filter(|(piece, _, _)| *piece == ChessPiece::Pawn)
+            .fold(0, |hash, (piece, side, square)| hash ^ zobrist::piece_key(piece, *side, *square))
+    }
+
+    //Puts the piece on the square or takes it off in both keys
+    fn toggle_piece_hash(&mut self, piece: &ChessPiece, side: Side, square: (i8, i8)) {
+        let key = zobrist::piece_key(piece, side, square);
+        self.hash ^= key;
+        if *piece == ChessPiece::Pawn {self.pawn_hash ^= key;}
+    }
+
+    //Same xors take a move on and off again
+    fn toggle_move_hash(&mut self, piece: &ChessPiece, side: Side, from: (i8, i8), to: (i8, i8), captured: Option<(ChessPiece, Side)>) {
+        self.toggle_piece_hash(piece, side, from);
+        self.toggle_piece_hash(piece, side, to);
+        if let Some((captured, captured_side)) = captured {
+            self.toggle_piece_hash(&captured, captured_side, to);
+        }
+    }
+
+    //Called before a move changes the hash
+    fn record_history(&mut self, piece: ChessPiece, capture: bool) {
+        self.history.push((self.hash, self.reversible_plies));
+        self.reversible_plies = if capture || piece == ChessPiece::Pawn {0} else {self.reversible_plies + 1};
+    }
+
+    /// Whether the position, with the same side to move, already came up since the last
+    /// capture or pawn move, earlier in the game or along the moves made in a search.
+    pub fn is_repetition(&self) -> bool {
+        let reversible = (self.reversible_plies as usize).min(self.history.len());
+        self.history[self.history.len() - reversible..].iter()
+            .rev()
+            .skip(1)
+            .step_by(2)
+            .any(|(hash, _)| *hash == self.hash)
+    }
+
+    /// Passes the turn. Nothing before a null move counts as a repetition, the positions
+    /// played before it had the other side to move.
+    pub fn make_null_move(&mut self) {
+        self.history.push((self.hash, self.reversible_plies));
+        self.reversible_plies = 0;
+    }
+
+    pub fn unmake_null_move(&mut self) {
+        if let Some((_, reversible_plies)) = self.history.pop() {
+            self.reversible_plies = reversible_plies;
+        }
+    }
+
     pub fn get_occupied_slots(&self) -> Vec<Vec<bool>> {
         let mut bitfield: Vec<Vec<bool>> = vec![];
         for row in self.board.iter() {
@@ -364,12 +550,24 @@ impl Board {
     }
 
     pub fn unmake_move(&mut self, l_move: &Move) -> bool {
-        let unmake_move = Move {
-            from: l_move.to,
-            to: l_move.from
+        let Move {from, to} = l_move;
+        let (piece, side) = match self.board[to.1 as usize][to.0 as usize].get_piece_side() {
+            Some(piece_side) => piece_side,
+            None => return false
         };
 
-        self.make_move(&unmake_move)
+        self.board[from.1 as usize][from.0 as usize].modify_cell(CellState::Piece(piece), Some(side));
+        if let Some((_, reversible_plies)) = self.history.pop() {
+            self.reversible_plies = reversible_plies;
+        }
+        let captured = self.captured_pieces.pop().flatten();
+        self.toggle_move_hash(&piece, side, *from, *to, captured);
+        let to_cell = &mut self.board[to.1 as usize][to.0 as usize];
+        match captured {
+            Some((captured, captured_side)) => to_cell.modify_cell(CellState::Piece(captured), Some(captured_side)),
+            None => to_cell.modify_cell(CellState::Empty, None)
+        }
+        true
     }
 
     pub fn make_move(&mut self, l_move: &Move) -> bool {
@@ -381,6 +579,11 @@ impl Board {
             None => return false
         };
         if let CellState::Piece(piece) = origin_cell.state {
+            let captured = self.board[to.1 as usize][to.0 as usize].get_piece_side();
+            self.captured_pieces.push(captured);
+            self.record_history(piece, captured.is_some());
+            self.toggle_move_hash(&piece, side, *from, *to, captured);
+
             let origin_cell_mut = &mut self.board[from.1 as usize][from.0 as usize];
             origin_cell_mut.modify_cell(CellState::Empty, None);
 
@@ -393,6 +596,110 @@ impl Board {
         false
     }
 
+    pub fn captured_piece(&self, l_move: &Move) -> Option<ChessPiece> {
+        Self::get_piece(&self.board, l_move.to)
+    }
+
+    pub fn find_king(&self, side: Side) -> Option<(i8, i8)> {
+        self.get_sides_boardpieces(side).into_iter()
+            .find(|board_piece| matches!(board_piece.piece, ChessPiece::King))
+            .map(|board_piece| board_piece.location)
+    }
+
+    pub fn piece_on(&self, square: (i8, i8)) -> Option<(ChessPiece, Side)> {
+        if !(0..8).contains(&square.0) || !(0..8).contains(&square.1) {return None;}
+        self.board[square.1 as usize][square.0 as usize].get_piece_side()
+    }
+
+    //Looks outwards from the square instead of generating every move of by_side.
+    //Pawns only ever capture straight ahead, the same way get_pseudolegal_moves moves them
+    pub fn is_square_attacked(&self, square: (i8, i8), by_side: Side) -> bool {
+        let has_attacker = |offset: (i8, i8), pieces: &[ChessPiece]| {
+            match self.piece_on((square.0 + offset.0, square.1 + offset.1)) {
+                Some((piece, side)) => side == by_side && pieces.contains(&piece),
+                None => false
+            }
+        };
+
+        let pawn_offset = match by_side {
+            Side::White => (0, 1),
+            Side::Black => (0, -1)
+        };
+        if has_attacker(pawn_offset, &[ChessPiece::Pawn]) {return true;}
+        if KNIGHT_OFFSETS.iter().any(|offset| has_attacker(*offset, &[ChessPiece::Knight])) {return true;}
+
+        for (direction, increment) in RAY_INCREMENTS.iter().enumerate() {
+            if has_attacker(*increment, &[ChessPiece::King]) {return true;}
+
+            //Even directions are N, E, S, W
+            let slider = if direction % 2 == 0 {ChessPiece::Rook} else {ChessPiece::Bishop};
+            let mut ray_square = (square.0 + increment.0, square.1 + increment.1);
+            while (0..8).contains(&ray_square.0) && (0..8).contains(&ray_square.1) {
+                if let Some((piece, side)) = self.piece_on(ray_square) {
+                    if side == by_side && (piece == slider || piece == ChessPiece::Queen) {return true;}
+                    break;
+                }
+                ray_square = (ray_square.0 + increment.0, ray_square.1 + increment.1);
+            }
+        }
+        false
+    }
+
+    //Every piece of by_side attacking the square. Sliders look through the squares in `removed`,
+    //so pieces lined up behind an attacker that already captured show up as well
+    pub fn attackers(&self, square: (i8, i8), by_side: Side, removed: &[(i8, i8)]) -> Vec<(ChessPiece, (i8, i8))> {
+        let attacker_at = |offset: (i8, i8), pieces: &[ChessPiece]| {
+            let attacker_square = (square.0 + offset.0, square.1 + offset.1);
+            if removed.contains(&attacker_square) {return None;}
+            match self.piece_on(attacker_square) {
+                Some((piece, side)) if side == by_side && pieces.contains(&piece) => Some((piece, attacker_square)),
+                _ => None
+            }
+        };
+        let mut attackers: Vec<(ChessPiece, (i8, i8))> = vec![];
+
+        let pawn_offset = match by_side {
+            Side::White => (0, 1),
+            Side::Black => (0, -1)
+        };
+        attackers.extend(attacker_at(pawn_offset, &[ChessPiece::Pawn]));
+        attackers.extend(KNIGHT_OFFSETS.iter().filter_map(|offset| attacker_at(*offset, &[ChessPiece::Knight])));
+
+        for (direction, increment) in RAY_INCREMENTS.iter().enumerate() {
+            attackers.extend(attacker_at(*increment, &[ChessPiece::King]));
+
+            let slider = if direction % 2 == 0 {ChessPiece::Rook} else {ChessPiece::Bishop};
+            let mut ray_square = (square.0 + increment.0, square.1 + increment.1);
+            while (0..8).contains(&ray_square.0) && (0..8).contains(&ray_square.1) {
+                if !removed.contains(&ray_square) {
+                    if let Some((piece, side)) = self.piece_on(ray_square) {
+                        if side == by_side && (piece == slider || piece == ChessPiece::Queen) {attackers.push((piece, ray_square));}
+                        break;
+                    }
+                }
+                ray_square = (ray_square.0 + increment.0, ray_square.1 + increment.1);
+            }
+        }
+        attackers
+    }
+
+    pub fn is_in_check(&self, side: Side) -> bool {
+        let opponent = match side {
+            Side::White => Side::Black,
+            Side::Black => Side::White
+        };
+        match self.find_king(side) {
+            Some(king) => self.is_square_attacked(king, opponent),
+            None => false
+        }
+    }
+
+    //Anything besides king and pawns, used to guard against zugzwang
+    pub fn has_non_pawn_material(&self, side: Side) -> bool {
+        self.get_sides_boardpieces(side).iter()
+            .any(|board_piece| !matches!(board_piece.piece, ChessPiece::King | ChessPiece::Pawn))
+    }
+
     //TODO Maybe make this return a Result
     pub fn move_piece(&mut self, origin: (i8, i8), to: (i8, i8), whose_turn: Side) -> bool {
         let origin_cell: &Cell = &self.board[origin.1 as usize][origin.0 as usize];
@@ -406,6 +713,9 @@ impl Board {
             let pseudolegal_moves = ChessPiece::get_pseudolegal_moves(&self.board, origin, &piece, &whose_turn);
             println!("legal_moves: {:?}", pseudolegal_moves);
             if pseudolegal_moves.contains(&to) {
+                let captured = self.board[to.1 as usize][to.0 as usize].get_piece_side();
+                self.record_history(piece, captured.is_some());
+                self.toggle_move_hash(&piece, side, origin, to, captured);
                 let origin_cell_mut = &mut self.board[origin.1 as usize][origin.0 as usize];
                 origin_cell_mut.modify_cell(CellState::Empty, None);
 
@@ -455,3 +765,105 @@ impl Board {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(board: &Board, whose_turn: Side) -> i32 {
+        Board::evaluate_board_score(board.get_board_state(), whose_turn)
+    }
+
+    #[test]
+    fn start_position_is_level() {
+        let board = Board::start_position();
+        assert_eq!(score(&board, Side::White), 0);
+        assert_eq!(score(&board, Side::Black), 0);
+        //Same keys as a board filled all at once
+        assert_eq!(board.hash(), board.compute_hash());
+        assert_eq!(board.pawn_hash(), board.compute_pawn_hash());
+    }
+
+    #[test]
+    fn queen_for_pawn_is_not_a_pawn_trade() {
+        let mut queen_for_pawn = Board::start_position();
+        queen_for_pawn.set_piece((4, 0), None);
+        queen_for_pawn.set_piece((4, 6), None);
+        let mut pawn_trade = Board::start_position();
+        pawn_trade.set_piece((4, 1), None);
+        pawn_trade.set_piece((4, 6), None);
+
+        assert_eq!(score(&queen_for_pawn, Side::White), 450);
+        assert_eq!(score(&pawn_trade, Side::White), 0);
+    }
+
+    #[test]
+    fn score_is_from_the_side_to_move() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (3, 7)),
+            (ChessPiece::King, Side::Black, (3, 0)),
+            (ChessPiece::Rook, Side::Black, (0, 0)),
+            (ChessPiece::Knight, Side::White, (5, 5)),
+            (ChessPiece::Pawn, Side::White, (6, 6))
+        ]);
+
+        //Rook against knight and pawn
+        assert_eq!(score(&board, Side::Black), 100);
+        assert_eq!(score(&board, Side::White), -100);
+    }
+
+    #[test]
+    fn extra_pieces_add_up() {
+        let mut board = Board::start_position();
+        for x in 0..8 {
+            board.set_piece((x, 1), None);
+        }
+        board.set_piece((1, 0), None);
+
+        //Eight pawns and a knight
+        assert_eq!(score(&board, Side::White), 8 * 50 + 250);
+        assert_eq!(score(&board, Side::Black), -(8 * 50 + 250));
+    }
+
+    fn rooks_and_kings() -> Board {
+        Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (3, 7)),
+            (ChessPiece::King, Side::Black, (3, 0)),
+            (ChessPiece::Rook, Side::White, (0, 7)),
+            (ChessPiece::Rook, Side::Black, (7, 0))
+        ])
+    }
+
+    fn play(board: &mut Board, moves: &[Move]) {
+        for l_move in moves {
+            assert!(board.make_move(l_move));
+        }
+    }
+
+    fn mv(from: (i8, i8), to: (i8, i8)) -> Move {
+        Move { from, to }
+    }
+
+    #[test]
+    fn moving_back_and_forth_repeats() {
+        let mut board = rooks_and_kings();
+        play(&mut board, &[mv((0, 7), (0, 6)), mv((7, 0), (7, 1)), mv((0, 6), (0, 7)), mv((7, 1), (7, 0))]);
+        assert!(board.is_repetition());
+    }
+
+    #[test]
+    fn null_move_is_a_repetition_barrier() {
+        let mut board = rooks_and_kings();
+        play(&mut board, &[mv((0, 7), (0, 6)), mv((7, 0), (7, 1))]);
+        board.make_null_move();
+        //Same pieces as at the start, but black is to move now
+        play(&mut board, &[mv((7, 1), (7, 0)), mv((0, 6), (0, 7))]);
+        assert!(!board.is_repetition());
+
+        board.unmake_move(&mv((0, 6), (0, 7)));
+        board.unmake_move(&mv((7, 1), (7, 0)));
+        board.unmake_null_move();
+        play(&mut board, &[mv((0, 6), (0, 7)), mv((7, 1), (7, 0))]);
+        assert!(board.is_repetition());
+    }
+}
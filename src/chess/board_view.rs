@@ -2,9 +2,12 @@ use super::board;
 use std::cell::RefCell;
 use macroquad::prelude::*;
 use super::board::{CELL_SIZE, Board, CellPiece};
+use super::difficulty::Difficulty;
+use super::minimax::SearchInfo;
 use super::{piece::{ChessPiece, Side}};
 
 const LEGAL_MOVE_CELL_COLOR: Color = Color::new(0.31, 0.54, 0.38, 0.7);
+const STATUS_FONT_SIZE: u16 = 24;
 
 #[derive(Clone)]
 pub struct PiecePickup {
@@ -109,6 +112,38 @@ impl BoardView {
         None
     }
 
+    //Status text in white, `y` is the baseline
+    fn draw_status_line(&self, text: &str, y: f32) {
+        let status_params = TextParams {
+            font_size: STATUS_FONT_SIZE,
+            color: WHITE,
+            ..self.text_params
+        };
+        draw_text_ex(text, 10.0, y, status_params);
+    }
+
+    pub fn draw_thinking_indicator(&self, engine_name: &str, info: Option<&SearchInfo>) {
+        let dots = ".".repeat((get_time() * 2.0) as usize % 4);
+        self.draw_status_line(&format!("{} is thinking{} (Esc to stop)", engine_name, dots), STATUS_FONT_SIZE as f32);
+
+        if let Some(info) = info {
+            let progress = format!("depth {}  score {}  nodes {}  nps {}", info.depth, info.score, info.nodes, info.nps);
+            self.draw_status_line(&progress, STATUS_FONT_SIZE as f32 * 2.0);
+        }
+    }
+
+    pub fn draw_pondering_indicator(&self, engine_name: &str) {
+        self.draw_status_line(&format!("{} is pondering on your time", engine_name), STATUS_FONT_SIZE as f32);
+    }
+
+    pub fn draw_game_over(&self, message: &str) {
+        self.draw_status_line(message, STATUS_FONT_SIZE as f32);
+    }
+
+    pub fn draw_difficulty(&self, difficulty: Difficulty) {
+        self.draw_status_line(&format!("Level: {} (1-{} to change)", difficulty.name(), Difficulty::ALL.len()), screen_height() - 10.0);
+    }
+
     pub fn player_input(&mut self, board: &mut Board) -> bool {
         match &self.picked_up_piece {
             Some(piece_pickup) => {
@@ -0,0 +1,149 @@
+use std::time::Duration;
+
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use super::minimax::PvLine;
+
+/// Root moves searched at the weaker levels, the candidates a sub-optimal move is picked from.
+pub const CANDIDATE_LINES: usize = 4;
+
+/// Playing strength, from beginner to full strength.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Difficulty {
+    Beginner,
+    Novice,
+    Intermediate,
+    Advanced,
+    Full
+}
+
+/// How a level holds the engine back. Scores are in material units, a pawn is 50.
+#[derive(Clone, Debug)]
+pub struct DifficultySettings {
+    pub depth: u8,
+    pub movetime: Duration,
+    /// Root move scores are shifted by up to this much either way before choosing.
+    pub eval_noise: i32,
+    /// Chance of passing over the best move for another one within `sub_optimal_margin`.
+    pub sub_optimal_chance: f64,
+    pub sub_optimal_margin: i32
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 5] = [
+        Difficulty::Beginner,
+        Difficulty::Novice,
+        Difficulty::Intermediate,
+        Difficulty::Advanced,
+        Difficulty::Full
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Beginner => "Beginner",
+            Difficulty::Novice => "Novice",
+            Difficulty::Intermediate => "Intermediate",
+            Difficulty::Advanced => "Advanced",
+            Difficulty::Full => "Full strength"
+        }
+    }
+
+    pub fn settings(self) -> DifficultySettings {
+        let (depth, movetime_ms, eval_noise, sub_optimal_chance, sub_optimal_margin) = match self {
+            Difficulty::Beginner => (1, 500, 150, 0.5, 250),
+            Difficulty::Novice => (2, 1000, 75, 0.3, 150),
+            Difficulty::Intermediate => (4, 2000, 25, 0.15, 50),
+            Difficulty::Advanced => (6, 3000, 10, 0.05, 25),
+            Difficulty::Full => (10, 5000, 0, 0.0, 0)
+        };
+        DifficultySettings {
+            depth,
+            movetime: Duration::from_millis(movetime_ms),
+            eval_noise,
+            sub_optimal_chance,
+            sub_optimal_margin
+        }
+    }
+}
+
+impl DifficultySettings {
+    /// Whether the engine has to search more than the best move.
+    pub fn is_weakened(&self) -> bool {
+        self.eval_noise > 0 || self.sub_optimal_chance > 0.0
+    }
+
+    /// Index of the line to play out of `lines`, which are ordered best first.
+    pub fn choose_line(&self, lines: &[PvLine], rng: &mut StdRng) -> usize {
+        if lines.len() < 2 {return 0;}
+
+        //Mates are left alone, noise should not make the engine miss or walk into one
+        let noisy_scores: Vec<i32> = lines.iter()
+            .map(|line| if line.score.is_mate() || self.eval_noise == 0 {
+                line.score.0
+            } else {
+                line.score.0 + rng.gen_range(-self.eval_noise..=self.eval_noise)
+            })
+            .collect();
+        let best = (0..lines.len()).max_by_key(|index| noisy_scores[*index]).unwrap_or(0);
+
+        if !rng.gen_bool(self.sub_optimal_chance) {return best;}
+        let alternatives: Vec<usize> = (0..lines.len())
+            .filter(|index| *index != best && noisy_scores[*index] >= noisy_scores[best] - self.sub_optimal_margin)
+            .collect();
+        if alternatives.is_empty() {return best;}
+        alternatives[rng.gen_range(0..alternatives.len())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use super::super::score::Score;
+
+    fn lines(scores: &[i32]) -> Vec<PvLine> {
+        scores.iter().map(|score| PvLine { score: Score(*score), pv: vec![] }).collect()
+    }
+
+    #[test]
+    fn full_strength_always_plays_the_best_line() {
+        let settings = Difficulty::Full.settings();
+        let lines = lines(&[120, 118, 40, -300]);
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..1_000 {
+            assert_eq!(settings.choose_line(&lines, &mut rng), 0);
+        }
+    }
+
+    #[test]
+    fn sub_optimal_picks_stay_within_the_margin() {
+        let settings = DifficultySettings { eval_noise: 0, sub_optimal_chance: 1.0, sub_optimal_margin: 40, ..Difficulty::Novice.settings() };
+        let lines = lines(&[100, 80, 60, 59, -200]);
+        let mut rng = StdRng::seed_from_u64(5);
+        let mut chosen = [0; 5];
+        for _ in 0..1_000 {
+            chosen[settings.choose_line(&lines, &mut rng)] += 1;
+        }
+        //The best line is always passed over, for the two within 40 of it and nothing further down
+        assert_eq!(chosen[0], 0);
+        assert!(chosen[1] > 0 && chosen[2] > 0);
+        assert_eq!(chosen[3..], [0, 0]);
+    }
+
+    #[test]
+    fn weak_levels_never_drop_further_than_margin_and_noise() {
+        let lines = lines(&[200, 150, 0, -100]);
+        let mut rng = StdRng::seed_from_u64(9);
+        for difficulty in Difficulty::ALL {
+            let settings = difficulty.settings();
+            //Noise can move the best line down and a worse one up by eval_noise each
+            let worst_allowed = lines[0].score.0 - settings.sub_optimal_margin - 2 * settings.eval_noise;
+            for _ in 0..1_000 {
+                let chosen = settings.choose_line(&lines, &mut rng);
+                assert!(lines[chosen].score.0 >= worst_allowed, "{:?} chose {}", difficulty, lines[chosen].score.0);
+            }
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Side, Board, swap_turn};
+use super::board::Move;
+use super::difficulty::{Difficulty, DifficultySettings, CANDIDATE_LINES};
+use super::evaluation::{self, EvalParams};
+use super::mcts::{self, MctsParams};
+use super::polyglot::{BookChoice, OpeningBook};
+use super::minimax::{search, SearchInfo, SearchLimits, SearchParams, SearchResult, PvLine};
+use super::pawns::PawnTable;
+use super::score::Score;
+use super::tablebase::Tablebases;
+use super::transposition::TranspositionTable;
+
+/// A move chooser the game loop can plug in.
+pub trait Engine: Send {
+    fn name(&self) -> &str;
+    /// Drops everything learned from earlier games and restarts the random generator
+    /// from the engine's seed, so a game can be replayed move for move.
+    fn new_game(&mut self);
+    fn set_position(&mut self, board: &Board, whose_turn: Side);
+    /// Engines without a strength setting play the same at every level,
+    /// only the depth and time caps in the limits hold them back.
+    fn set_difficulty(&mut self, _difficulty: Difficulty) {}
+    /// How much the engine avoids draws, see `SearchParams::contempt`. Engines that do not
+    /// score draws ignore it.
+    fn set_contempt(&mut self, _contempt: i32) {}
+    /// Chooses a move for the position last set, `None` when the side to move has no legal move.
+    /// `on_iteration` gets the progress of engines that search in steps.
+    fn think(&mut self, limits: &SearchLimits, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult>;
+}
+
+/// The alpha-beta search, keeping its transposition table between moves.
+pub struct MinimaxEngine {
+    params: SearchParams,
+    table: TranspositionTable,
+    position: Option<(Board, Side)>,
+    difficulty: DifficultySettings,
+    rng: StdRng
+}
+
+impl MinimaxEngine {
+    /// Starts at full strength with an empty table of `params.hash_size_mb`.
+    pub fn new(params: SearchParams) -> MinimaxEngine {
+        let table = TranspositionTable::new(params.hash_size_mb);
+        let rng = StdRng::seed_from_u64(params.seed);
+        MinimaxEngine { params, table, position: None, difficulty: Difficulty::Full.settings(), rng }
+    }
+}
+
+impl Engine for MinimaxEngine {
+    fn name(&self) -> &str {
+        "minimax"
+    }
+
+    fn new_game(&mut self) {
+        self.table.clear();
+        self.rng = StdRng::seed_from_u64(self.params.seed);
+    }
+
+    fn set_position(&mut self, board: &Board, whose_turn: Side) {
+        self.position = Some((board.clone(), whose_turn));
+    }
+
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty.settings();
+    }
+
+    fn set_contempt(&mut self, contempt: i32) {
+        self.params.contempt = contempt;
+    }
+
+    fn think(&mut self, limits: &SearchLimits, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+        let (board, whose_turn) = self.position.as_ref()?;
+        let mut params = SearchParams {
+            seed: self.rng.gen(),
+            ..self.params.clone()
+        };
+        if !self.difficulty.is_weakened() {
+            return search(*whose_turn, board, &params, &self.table, limits, on_iteration);
+        }
+
+        //Weaker levels search a few candidates and may settle for one of the worse ones
+        params.multi_pv = params.multi_pv.max(CANDIDATE_LINES);
+        let mut result = search(*whose_turn, board, &params, &self.table, limits, on_iteration)?;
+        let chosen = self.difficulty.choose_line(&result.info.lines, &mut self.rng);
+        if let Some(line) = result.info.lines.get(chosen).filter(|line| !line.pv.is_empty()) {
+            result.best_move = line.pv[0].clone();
+            result.info.score = line.score;
+            result.info.pv = line.pv.clone();
+        }
+        Some(result)
+    }
+}
+
+/// Monte Carlo tree search, a new tree for every move.
+pub struct MctsEngine {
+    params: MctsParams,
+    position: Option<(Board, Side)>,
+    rng: StdRng
+}
+
+impl MctsEngine {
+    pub fn new(params: MctsParams) -> MctsEngine {
+        let rng = StdRng::seed_from_u64(params.seed);
+        MctsEngine { params, position: None, rng }
+    }
+}
+
+impl Engine for MctsEngine {
+    fn name(&self) -> &str {
+        "mcts"
+    }
+
+    fn new_game(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.params.seed);
+    }
+
+    fn set_position(&mut self, board: &Board, whose_turn: Side) {
+        self.position = Some((board.clone(), whose_turn));
+    }
+
+    fn think(&mut self, limits: &SearchLimits, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+        let (board, whose_turn) = self.position.as_ref()?;
+        let params = MctsParams {
+            seed: self.rng.gen(),
+            ..self.params.clone()
+        };
+        mcts::search(*whose_turn, board, &params, limits, on_iteration)
+    }
+}
+
+//Result of a move picked without searching, `nodes` positions were looked at
+fn single_move_result(best_move: Move, score: Score, nodes: u64, started: Instant) -> SearchResult {
+    let pv = vec![best_move.clone()];
+    let mut info = SearchInfo {
+        depth: 1,
+        seldepth: 1,
+        nodes,
+        score,
+        pv: pv.clone(),
+        lines: vec![PvLine { score, pv }],
+        elapsed: started.elapsed(),
+        ..Default::default()
+    };
+    info.nps = (nodes as f64 / info.elapsed.as_secs_f64().max(0.001)) as u64;
+    SearchResult { best_move, info, trace: None }
+}
+
+/// Plays any legal move.
+pub struct RandomEngine {
+    seed: u64,
+    position: Option<(Board, Side)>,
+    rng: StdRng
+}
+
+impl RandomEngine {
+    pub fn new(seed: u64) -> RandomEngine {
+        RandomEngine { seed, position: None, rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl Engine for RandomEngine {
+    fn name(&self) -> &str {
+        "random"
+    }
+
+    fn new_game(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    fn set_position(&mut self, board: &Board, whose_turn: Side) {
+        self.position = Some((board.clone(), whose_turn));
+    }
+
+    fn think(&mut self, _limits: &SearchLimits, _on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+        let started = Instant::now();
+        let (board, whose_turn) = self.position.as_mut()?;
+        let mut moves = board.get_legal_moves_for_side(*whose_turn);
+        if moves.is_empty() {return None;}
+        let random_move = moves.swap_remove(self.rng.gen_range(0..moves.len()));
+        Some(single_move_result(random_move, Score::default(), 1, started))
+    }
+}
+
+/// Plays the move with the best evaluation right after it, ties broken at random.
+pub struct GreedyEngine {
+    seed: u64,
+    eval: EvalParams,
+    pawns: PawnTable,
+    position: Option<(Board, Side)>,
+    rng: StdRng
+}
+
+impl GreedyEngine {
+    pub fn new(seed: u64) -> GreedyEngine {
+        GreedyEngine {
+            seed,
+            eval: EvalParams::default(),
+            pawns: PawnTable::default(),
+            position: None,
+            rng: StdRng::seed_from_u64(seed)
+        }
+    }
+}
+
+impl Engine for GreedyEngine {
+    fn name(&self) -> &str {
+        "greedy"
+    }
+
+    fn new_game(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+    }
+
+    fn set_position(&mut self, board: &Board, whose_turn: Side) {
+        self.position = Some((board.clone(), whose_turn));
+    }
+
+    fn think(&mut self, _limits: &SearchLimits, _on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+        let started = Instant::now();
+        let (board, whose_turn) = self.position.as_mut()?;
+        let moves = board.get_legal_moves_for_side(*whose_turn);
+        let nodes = moves.len() as u64;
+
+        let mut best: Vec<Move> = vec![];
+        let mut best_score = i32::MIN;
+        for l_move in moves {
+            board.make_move(&l_move);
+            let score = evaluation::evaluate(board, *whose_turn, &self.eval, &mut self.pawns);
+            board.unmake_move(&l_move);
+
+            if score > best_score {
+                best_score = score;
+                best.clear();
+            }
+            if score == best_score {best.push(l_move);}
+        }
+        if best.is_empty() {return None;}
+        let best_move = best.swap_remove(self.rng.gen_range(0..best.len()));
+        Some(single_move_result(best_move, Score(best_score), nodes, started))
+    }
+}
+
+/// Plays from an opening book while the position is in it and leaves the rest to `engine`.
+pub struct BookEngine {
+    book: OpeningBook,
+    choice: BookChoice,
+    engine: Box<dyn Engine>,
+    seed: u64,
+    position: Option<(Board, Side)>,
+    rng: StdRng
+}
+
+impl BookEngine {
+    pub fn new(book: OpeningBook, choice: BookChoice, engine: Box<dyn Engine>, seed: u64) -> BookEngine {
+        BookEngine { book, choice, engine, seed, position: None, rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl Engine for BookEngine {
+    fn name(&self) -> &str {
+        self.engine.name()
+    }
+
+    fn new_game(&mut self) {
+        self.rng = StdRng::seed_from_u64(self.seed);
+        self.engine.new_game();
+    }
+
+    fn set_position(&mut self, board: &Board, whose_turn: Side) {
+        self.position = Some((board.clone(), whose_turn));
+        self.engine.set_position(board, whose_turn);
+    }
+
+    fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.engine.set_difficulty(difficulty);
+    }
+
+    fn set_contempt(&mut self, contempt: i32) {
+        self.engine.set_contempt(contempt);
+    }
+
+    fn think(&mut self, limits: &SearchLimits, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+        let started = Instant::now();
+        let (board, whose_turn) = self.position.as_mut()?;
+        match self.book.choose(board, *whose_turn, self.choice, &mut self.rng) {
+            Some(book_move) => Some(single_move_result(book_move, Score::default(), 0, started)),
+            None => self.engine.think(limits, on_iteration)
+        }
+    }
+}
+
+/// Options handed to the engine `EngineKind::create` builds, each engine takes the ones it supports.
+#[derive(Clone, Debug, Default)]
+pub struct EngineConfig {
+    /// Every random choice the engine makes comes from it. Searching engines seed the whole
+    /// game with it, every search gets a seed of its own drawn from it.
+    pub seed: u64,
+    /// Probed by minimax.
+    pub tablebases: Option<Arc<Tablebases>>,
+    /// Plies of minimax's tree recorded with every result.
+    pub trace_depth: Option<usize>,
+    /// Lazy SMP threads minimax searches with, every core when not set.
+    pub threads: Option<usize>,
+    /// Best root moves minimax searches and reports, just the best one when not set.
+    pub multi_pv: Option<usize>
+}
+
+/// Engines that can be picked at runtime, by name on the command line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineKind {
+    Minimax,
+    Mcts,
+    Random,
+    Greedy
+}
+
+impl EngineKind {
+    pub fn from_name(name: &str) -> Option<EngineKind> {
+        match name {
+            "minimax" => Some(EngineKind::Minimax),
+            "mcts" => Some(EngineKind::Mcts),
+            "random" => Some(EngineKind::Random),
+            "greedy" => Some(EngineKind::Greedy),
+            _ => None
+        }
+    }
+
+    pub fn create(self, config: EngineConfig) -> Box<dyn Engine> {
+        let seed = config.seed;
+        match self {
+            EngineKind::Minimax => Box::new(MinimaxEngine::new(SearchParams {
+                threads: config.threads.unwrap_or_else(|| thread::available_parallelism().map_or(1, |threads| threads.get())),
+                seed,
+                tablebases: config.tablebases,
+                trace_depth: config.trace_depth,
+                multi_pv: config.multi_pv.unwrap_or(1),
+                ..Default::default()
+            })),
+            EngineKind::Mcts => Box::new(MctsEngine::new(MctsParams { seed, ..Default::default() })),
+            EngineKind::Random => Box::new(RandomEngine::new(seed)),
+            EngineKind::Greedy => Box::new(GreedyEngine::new(seed))
+        }
+    }
+}
+
+/// Why the game ended once `side` has no legal move left.
+pub fn game_over_message(board: &Board, side: Side) -> String {
+    if board.is_in_check(side) {
+        format!("Checkmate, {:?} has lost", side)
+    } else {
+        format!("Stalemate, {:?} has no moves left", side)
+    }
+}
+
+/// Plays the move chosen by the engine on the displayed board and returns whose turn it is
+/// next, `None` once the game is over because the engine had no move to play.
+pub fn opponents_turn(board: &mut Board, result: Option<SearchResult>, whose_turn: Side) -> Option<Side> {
+    match result {
+        Some(result) => {
+            let best_move = result.best_move;
+            println!("best_move, from: ({}, {}), to: ({}, {})", best_move.from.0, best_move.from.1, best_move.to.0, best_move.to.1);
+            board.move_piece(best_move.from, best_move.to, whose_turn);
+            Some(swap_turn(whose_turn))
+        },
+        None => {
+            println!("{}", game_over_message(board, whose_turn));
+            None
+        }
+    }
+}
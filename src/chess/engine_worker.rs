@@ -0,0 +1,447 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use crate::swap_turn;
+use super::board::{Board, Move};
+use super::difficulty::Difficulty;
+use super::engine::Engine;
+use super::minimax::{format_info, SearchInfo, SearchLimits, SearchResult};
+use super::piece::Side;
+
+//How often a finished ponder search looks whether the opponent has moved yet
+const PONDER_POLL_INTERVAL: Duration = Duration::from_millis(5);
+//Opponent moves seen before their strength is estimated
+const CONTEMPT_MIN_REPLIES: u32 = 4;
+//Contempt against an opponent who never plays the reply the engine expects, half a pawn
+const MAX_CONTEMPT: i32 = 25;
+
+enum EngineRequest {
+    //Every search gets its own stop flag, so stopping one can never reach the next
+    Think { board: Board, whose_turn: Side, stop: Arc<AtomicBool> },
+    Ponder { board: Board, whose_turn: Side, stop: Arc<AtomicBool>, pondering: Arc<AtomicBool> },
+    SetDifficulty(Difficulty),
+    SetContempt(i32),
+    SetTimeLimits(bool)
+}
+
+enum EngineReply {
+    Info(SearchInfo),
+    BestMove(Option<SearchResult>)
+}
+
+//Search running on the opponent's time in the position after the predicted reply
+struct Ponder {
+    key: u64,
+    whose_turn: Side,
+    pondering: Arc<AtomicBool>
+}
+
+/// Runs the engine on its own thread so the window keeps redrawing while it searches.
+pub struct EngineWorker {
+    requests: Sender<EngineRequest>,
+    replies: Receiver<EngineReply>,
+    //Stop flag of the latest search
+    stop: Arc<AtomicBool>,
+    name: String,
+    difficulty: Difficulty,
+    ponder_enabled: bool,
+    //Reply the last search expected from the opponent
+    predicted_reply: Option<Move>,
+    //Position that reply leads to, compared with the one the opponent actually left
+    expected_key: Option<u64>,
+    //How often the opponent played the expected reply, the estimate of their strength
+    opponent_replies: u32,
+    expected_replies: u32,
+    //Set from the command line, otherwise estimated from the opponent's replies
+    fixed_contempt: Option<i32>,
+    contempt: i32,
+    ponder: Option<Ponder>,
+    thinking: bool,
+    //Latest progress of the running search
+    info: Option<SearchInfo>
+}
+
+fn run(mut engine: Box<dyn Engine>, requests: Receiver<EngineRequest>, replies: Sender<EngineReply>) {
+    engine.new_game();
+    let mut limits = SearchLimits::default();
+    //Move time of the difficulty level, only applied while time limits are on
+    let mut movetime = None;
+    let mut timed = true;
+
+    //Ends once the worker handle, and with it the request sender, is dropped
+    for request in requests {
+        match request {
+            EngineRequest::Think { board, whose_turn, stop } => {
+                let mut report = |info: &SearchInfo| {
+                    //The UI might be gone already, the final send below notices that
+                    let _ = replies.send(EngineReply::Info(info.clone()));
+                };
+                engine.set_position(&board, whose_turn);
+                let result = engine.think(&SearchLimits { stop, ..limits.clone() }, &mut report);
+                if let Some(result) = &result {
+                    println!("{}", format_info(&result.info));
+                }
+                if replies.send(EngineReply::BestMove(result)).is_err() {return;}
+            },
+            EngineRequest::Ponder { board, whose_turn, stop, pondering } => {
+                //Progress only matters once the guess turned out right
+                let mut report = |info: &SearchInfo| {
+                    if !pondering.load(Ordering::Relaxed) {
+                        let _ = replies.send(EngineReply::Info(info.clone()));
+                    }
+                };
+                engine.set_position(&board, whose_turn);
+                let ponder_limits = SearchLimits { stop: stop.clone(), ponder: pondering.clone(), ..limits.clone() };
+                let result = engine.think(&ponder_limits, &mut report);
+
+                //Done before the opponent moved, the move is held until it is clear whether the guess was right
+                while pondering.load(Ordering::Relaxed) && !stop.load(Ordering::Relaxed) {
+                    thread::sleep(PONDER_POLL_INTERVAL);
+                }
+                //Stopped while still pondering, the opponent played something else
+                if pondering.load(Ordering::Relaxed) {continue;}
+
+                if let Some(result) = &result {
+                    println!("ponder hit, {}", format_info(&result.info));
+                }
+                if replies.send(EngineReply::BestMove(result)).is_err() {return;}
+            },
+            EngineRequest::SetDifficulty(difficulty) => {
+                let settings = difficulty.settings();
+                limits.depth = settings.depth;
+                movetime = Some(settings.movetime);
+                limits.movetime = movetime.filter(|_| timed);
+                engine.set_difficulty(difficulty);
+            },
+            EngineRequest::SetContempt(contempt) => engine.set_contempt(contempt),
+            EngineRequest::SetTimeLimits(enabled) => {
+                timed = enabled;
+                limits.movetime = movetime.filter(|_| timed);
+            }
+        }
+    }
+}
+
+impl EngineWorker {
+    pub fn spawn(engine: Box<dyn Engine>) -> EngineWorker {
+        let (requests, request_receiver) = channel();
+        let (reply_sender, replies) = channel();
+
+        let name = engine.name().to_owned();
+        thread::spawn(move || run(engine, request_receiver, reply_sender));
+
+        let mut worker = EngineWorker {
+            requests,
+            replies,
+            stop: Arc::new(AtomicBool::new(false)),
+            name,
+            difficulty: Difficulty::Full,
+            ponder_enabled: false,
+            predicted_reply: None,
+            expected_key: None,
+            opponent_replies: 0,
+            expected_replies: 0,
+            fixed_contempt: None,
+            contempt: 0,
+            ponder: None,
+            thinking: false,
+            info: None
+        };
+        worker.set_difficulty(Difficulty::Full);
+        worker
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn difficulty(&self) -> Difficulty {
+        self.difficulty
+    }
+
+    /// Takes effect from the next move on.
+    pub fn set_difficulty(&mut self, difficulty: Difficulty) {
+        self.difficulty = difficulty;
+        let _ = self.requests.send(EngineRequest::SetDifficulty(difficulty));
+    }
+
+    /// `Some` fixes the contempt, `None` estimates it from how often the opponent plays
+    /// the reply the engine expected: draws are avoided against an opponent who rarely
+    /// does and welcomed against one who usually does.
+    pub fn set_contempt(&mut self, contempt: Option<i32>) {
+        self.fixed_contempt = contempt;
+        self.send_contempt(contempt.unwrap_or(0));
+    }
+
+    fn send_contempt(&mut self, contempt: i32) {
+        if contempt == self.contempt {return;}
+        self.contempt = contempt;
+        println!("Contempt {}", contempt);
+        let _ = self.requests.send(EngineRequest::SetContempt(contempt));
+    }
+
+    //The opponent just left the position the engine now has to move in
+    fn estimate_contempt(&mut self, board: &Board) {
+        let expected_key = match self.expected_key.take() {
+            Some(expected_key) => expected_key,
+            None => return
+        };
+        self.opponent_replies += 1;
+        if board.hash() == expected_key {self.expected_replies += 1;}
+        if self.fixed_contempt.is_some() || self.opponent_replies < CONTEMPT_MIN_REPLIES {return;}
+
+        let expected_rate = self.expected_replies as f64 / self.opponent_replies as f64;
+        self.send_contempt(((1.0 - 2.0 * expected_rate) * MAX_CONTEMPT as f64).round() as i32);
+    }
+
+    /// Turns the move time of the difficulty level on or off, takes effect from the next move on.
+    /// Without time limits a search only ends at the depth limit or when stopped, so on a
+    /// single thread the same seed and the same opponent moves give the same game.
+    pub fn set_time_limits(&mut self, enabled: bool) {
+        let _ = self.requests.send(EngineRequest::SetTimeLimits(enabled));
+    }
+
+    /// Lets the engine think on the opponent's time, see `ponder`.
+    pub fn set_pondering(&mut self, enabled: bool) {
+        self.ponder_enabled = enabled;
+    }
+
+    pub fn is_thinking(&self) -> bool {
+        self.thinking
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.ponder.is_some()
+    }
+
+    pub fn info(&self) -> Option<&SearchInfo> {
+        self.info.as_ref()
+    }
+
+    /// Call once the engine's move is on the board and `whose_turn` is the opponent's.
+    /// The engine notes the reply it expects and, when pondering is on, starts searching it.
+    pub fn ponder(&mut self, board: &Board, whose_turn: Side) {
+        let predicted_reply = match self.predicted_reply.take() {
+            Some(predicted_reply) => predicted_reply,
+            None => return
+        };
+        let mut ponder_board = board.clone();
+        if !ponder_board.make_move(&predicted_reply) {return;}
+        self.expected_key = Some(ponder_board.hash());
+        if !self.ponder_enabled {return;}
+
+        let engine_side = swap_turn(whose_turn);
+        let pondering = Arc::new(AtomicBool::new(true));
+        self.stop = Arc::new(AtomicBool::new(false));
+        self.ponder = Some(Ponder { key: ponder_board.hash(), whose_turn: engine_side, pondering: pondering.clone() });
+        let request = EngineRequest::Ponder { board: ponder_board, whose_turn: engine_side, stop: self.stop.clone(), pondering };
+        if self.requests.send(request).is_err() {
+            self.ponder = None;
+        }
+    }
+
+    pub fn start_thinking(&mut self, board: Board, whose_turn: Side) {
+        self.info = None;
+        self.estimate_contempt(&board);
+        if let Some(ponder) = self.ponder.take() {
+            if ponder.key == board.hash() && ponder.whose_turn == whose_turn {
+                //Ponder hit, the running search becomes the real one and its clock starts now
+                ponder.pondering.store(false, Ordering::Relaxed);
+                self.thinking = true;
+                return;
+            }
+            self.stop.store(true, Ordering::Relaxed);
+        }
+
+        self.stop = Arc::new(AtomicBool::new(false));
+        self.thinking = self.requests.send(EngineRequest::Think { board, whose_turn, stop: self.stop.clone() }).is_ok();
+    }
+
+    /// Asks the search to wrap up, the best move found so far still arrives through `try_receive`.
+    pub fn abort(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    /// Picks up progress reports and returns the search result once it is in.
+    pub fn try_receive(&mut self) -> Option<Option<SearchResult>> {
+        loop {
+            match self.replies.try_recv() {
+                Ok(EngineReply::Info(info)) => self.info = Some(info),
+                Ok(EngineReply::BestMove(result)) => {
+                    self.thinking = false;
+                    self.predicted_reply = result.as_ref().and_then(|result| result.info.pv.get(1).cloned());
+                    return Some(result);
+                },
+                Err(TryRecvError::Empty) => return None,
+                Err(TryRecvError::Disconnected) => {
+                    self.thinking = false;
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    use super::*;
+    use super::super::engine::MinimaxEngine;
+    use super::super::minimax::SearchParams;
+    use super::super::test_positions::{back_rank_mate, middlegame};
+
+    //Longest any test waits for the engine thread
+    const REPLY_TIMEOUT: Duration = Duration::from_secs(30);
+
+    fn receive(worker: &mut EngineWorker) -> Option<SearchResult> {
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Some(result) = worker.try_receive() {return result;}
+            thread::sleep(Duration::from_millis(1));
+        }
+        panic!("no reply from the engine thread");
+    }
+
+    fn minimax_worker() -> EngineWorker {
+        EngineWorker::spawn(Box::new(MinimaxEngine::new(SearchParams::default())))
+    }
+
+    //Plays the first legal move and expects the first legal reply, noting every position it is asked about
+    struct FirstMoveEngine {
+        position: Option<(Board, Side)>,
+        asked: Arc<Mutex<Vec<u64>>>
+    }
+
+    impl Engine for FirstMoveEngine {
+        fn name(&self) -> &str {
+            "first move"
+        }
+
+        fn new_game(&mut self) {}
+
+        fn set_position(&mut self, board: &Board, whose_turn: Side) {
+            self.position = Some((board.clone(), whose_turn));
+        }
+
+        fn think(&mut self, _limits: &SearchLimits, _on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+            let (board, whose_turn) = self.position.as_mut()?;
+            self.asked.lock().unwrap().push(board.hash());
+            let best_move = board.get_legal_moves_for_side(*whose_turn).into_iter().next()?;
+            board.make_move(&best_move);
+            let reply = board.get_legal_moves_for_side(swap_turn(*whose_turn)).into_iter().next();
+            board.unmake_move(&best_move);
+            let pv = std::iter::once(best_move.clone()).chain(reply).collect();
+            Some(SearchResult { best_move, info: SearchInfo { pv, ..Default::default() }, trace: None })
+        }
+    }
+
+    fn first_move_worker() -> (EngineWorker, Arc<Mutex<Vec<u64>>>) {
+        let asked = Arc::new(Mutex::new(vec![]));
+        let engine = FirstMoveEngine { position: None, asked: asked.clone() };
+        (EngineWorker::spawn(Box::new(engine)), asked)
+    }
+
+    #[test]
+    fn think_returns_a_move() {
+        let mut worker = minimax_worker();
+        worker.start_thinking(back_rank_mate(), Side::White);
+        assert!(worker.is_thinking());
+        let result = receive(&mut worker).expect("white has moves");
+        assert!(!worker.is_thinking());
+        assert_eq!(result.best_move, Move { from: (0, 7), to: (0, 0) });
+    }
+
+    #[test]
+    fn abort_returns_the_best_move_so_far_promptly() {
+        let mut worker = minimax_worker();
+        worker.set_time_limits(false);
+        let board = middlegame();
+        worker.start_thinking(board.clone(), Side::White);
+        thread::sleep(Duration::from_millis(50));
+
+        let aborted = Instant::now();
+        worker.abort();
+        let result = receive(&mut worker).expect("white has moves");
+        assert!(aborted.elapsed() < Duration::from_secs(2));
+        assert!(board.clone().get_legal_moves_for_side(Side::White).contains(&result.best_move));
+    }
+
+    //Engine plays white's first move and starts pondering on the reply it expects, which is returned
+    fn start_pondering(worker: &mut EngineWorker, board: &mut Board) -> Move {
+        worker.set_pondering(true);
+        worker.start_thinking(board.clone(), Side::White);
+        let result = receive(worker).expect("white has moves");
+        assert!(board.make_move(&result.best_move));
+        worker.ponder(board, Side::Black);
+        assert!(worker.is_pondering());
+        result.info.pv[1].clone()
+    }
+
+    #[test]
+    fn expected_reply_is_a_ponder_hit() {
+        let (mut worker, asked) = first_move_worker();
+        let mut board = middlegame();
+        let predicted_reply = start_pondering(&mut worker, &mut board);
+
+        assert!(board.make_move(&predicted_reply));
+        worker.start_thinking(board.clone(), Side::White);
+        assert!(!worker.is_pondering());
+        let result = receive(&mut worker).expect("white has moves");
+        assert!(board.get_legal_moves_for_side(Side::White).contains(&result.best_move));
+        //The ponder search became the real one, nothing else was searched
+        assert_eq!(asked.lock().unwrap()[1..], [board.hash()]);
+    }
+
+    #[test]
+    fn other_reply_is_a_ponder_miss() {
+        let (mut worker, asked) = first_move_worker();
+        let mut board = middlegame();
+        let predicted_reply = start_pondering(&mut worker, &mut board);
+
+        let other_reply = board.get_legal_moves_for_side(Side::Black).into_iter()
+            .find(|l_move| *l_move != predicted_reply)
+            .expect("black has more than one move");
+        assert!(board.make_move(&other_reply));
+        worker.start_thinking(board.clone(), Side::White);
+        let result = receive(&mut worker).expect("white has moves");
+        assert!(board.get_legal_moves_for_side(Side::White).contains(&result.best_move));
+        //The ponder search was dropped and the position actually on the board searched instead
+        let asked = asked.lock().unwrap();
+        assert_eq!(asked.len(), 3);
+        assert_ne!(asked[1], board.hash());
+        assert_eq!(asked[2], board.hash());
+    }
+
+    //Contempt once the opponent has answered CONTEMPT_MIN_REPLIES moves, always with the reply
+    //the engine expected or never with it
+    fn contempt_after_replies(expected_replies: bool, fixed_contempt: Option<i32>) -> i32 {
+        let (mut worker, _) = first_move_worker();
+        worker.set_contempt(fixed_contempt);
+        let mut board = middlegame();
+        for _ in 0..=CONTEMPT_MIN_REPLIES {
+            worker.start_thinking(board.clone(), Side::White);
+            let result = receive(&mut worker).expect("white has moves");
+            assert!(board.make_move(&result.best_move));
+            worker.ponder(&board, Side::Black);
+
+            let predicted_reply = &result.info.pv[1];
+            let reply = board.get_legal_moves_for_side(Side::Black).into_iter()
+                .find(|l_move| (l_move == predicted_reply) == expected_replies)
+                .expect("black has more than one move");
+            assert!(board.make_move(&reply));
+        }
+        worker.contempt
+    }
+
+    #[test]
+    fn contempt_is_estimated_from_the_opponents_replies() {
+        assert_eq!(contempt_after_replies(false, None), MAX_CONTEMPT);
+        assert_eq!(contempt_after_replies(true, None), -MAX_CONTEMPT);
+        assert_eq!(contempt_after_replies(false, Some(10)), 10);
+    }
+}
@@ -0,0 +1,329 @@
+use std::ops::{Add, AddAssign, Mul};
+
+use crate::{Side, Board};
+use super::activity::{self, ActivityParams};
+use super::king_safety::{self, KingSafetyParams};
+use super::pawns::{PawnParams, PawnTable};
+use super::piece::ChessPiece;
+
+/// Game phase with all the starting pieces on the board, it counts down to 0 as they come off.
+pub const MAX_PHASE: i32 = 24;
+//Share of the phase each piece carries, in ChessPiece order: king, queen, bishop, pawn, knight, rook
+const PHASE_WEIGHTS: [i32; 6] = [0, 4, 1, 0, 1, 2];
+
+//Tables are from white's point of view in board order, row 0 is black's back rank.
+//Black looks them up upside down. Values are in material units, a pawn is 50
+const PAWN_MIDDLEGAME: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     25,  25,  25,  25,  25,  25,  25,  25,
+      5,   5,  10,  15,  15,  10,   5,   5,
+      2,   2,   5,  12,  12,   5,   2,   2,
+      0,   0,   0,  10,  10,   0,   0,   0,
+      2,  -2,  -5,   0,   0,  -5,  -2,   2,
+      2,   5,   5, -10, -10,   5,   5,   2,
+      0,   0,   0,   0,   0,   0,   0,   0
+];
+const PAWN_ENDGAME: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+     40,  40,  40,  40,  40,  40,  40,  40,
+     25,  25,  25,  25,  25,  25,  25,  25,
+     15,  15,  15,  15,  15,  15,  15,  15,
+     10,  10,  10,  10,  10,  10,  10,  10,
+      5,   5,   5,   5,   5,   5,   5,   5,
+      5,   5,   5,   5,   5,   5,   5,   5,
+      0,   0,   0,   0,   0,   0,   0,   0
+];
+const KNIGHT_MIDDLEGAME: [i32; 64] = [
+    -25, -20, -15, -15, -15, -15, -20, -25,
+    -20, -10,   0,   0,   0,   0, -10, -20,
+    -15,   0,   5,   7,   7,   5,   0, -15,
+    -15,   2,   7,  10,  10,   7,   2, -15,
+    -15,   0,   7,  10,  10,   7,   0, -15,
+    -15,   2,   5,   7,   7,   5,   2, -15,
+    -20, -10,   0,   2,   2,   0, -10, -20,
+    -25, -20, -15, -15, -15, -15, -20, -25
+];
+const KNIGHT_ENDGAME: [i32; 64] = [
+    -25, -20, -15, -15, -15, -15, -20, -25,
+    -20, -10,   0,   0,   0,   0, -10, -20,
+    -15,   0,   5,   7,   7,   5,   0, -15,
+    -15,   0,   7,  10,  10,   7,   0, -15,
+    -15,   0,   7,  10,  10,   7,   0, -15,
+    -15,   0,   5,   7,   7,   5,   0, -15,
+    -20, -10,   0,   0,   0,   0, -10, -20,
+    -25, -20, -15, -15, -15, -15, -20, -25
+];
+const BISHOP_MIDDLEGAME: [i32; 64] = [
+    -10,  -5,  -5,  -5,  -5,  -5,  -5, -10,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   2,   5,   5,   2,   0,  -5,
+     -5,   2,   2,   5,   5,   2,   2,  -5,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+     -5,   5,   5,   5,   5,   5,   5,  -5,
+     -5,   2,   0,   0,   0,   0,   2,  -5,
+    -10,  -5,  -5,  -5,  -5,  -5,  -5, -10
+];
+const BISHOP_ENDGAME: [i32; 64] = [
+    -10,  -5,  -5,  -5,  -5,  -5,  -5, -10,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+     -5,   0,   5,   7,   7,   5,   0,  -5,
+     -5,   0,   5,   7,   7,   5,   0,  -5,
+     -5,   0,   5,   5,   5,   5,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+    -10,  -5,  -5,  -5,  -5,  -5,  -5, -10
+];
+const ROOK_MIDDLEGAME: [i32; 64] = [
+      0,   0,   0,   0,   0,   0,   0,   0,
+      2,   5,   5,   5,   5,   5,   5,   2,
+     -2,   0,   0,   0,   0,   0,   0,  -2,
+     -2,   0,   0,   0,   0,   0,   0,  -2,
+     -2,   0,   0,   0,   0,   0,   0,  -2,
+     -2,   0,   0,   0,   0,   0,   0,  -2,
+     -2,   0,   0,   0,   0,   0,   0,  -2,
+      0,   0,   0,   2,   2,   0,   0,   0
+];
+const ROOK_ENDGAME: [i32; 64] = [
+      5,   5,   5,   5,   5,   5,   5,   5,
+      5,   5,   5,   5,   5,   5,   5,   5,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0,
+      0,   0,   0,   0,   0,   0,   0,   0
+];
+const QUEEN_MIDDLEGAME: [i32; 64] = [
+    -10,  -5,  -5,  -2,  -2,  -5,  -5, -10,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   2,   2,   2,   2,   0,  -5,
+     -2,   0,   2,   2,   2,   2,   0,  -2,
+     -2,   0,   2,   2,   2,   2,   0,   0,
+     -5,   0,   2,   2,   2,   2,   2,  -5,
+     -5,   0,   0,   0,   0,   2,   0,  -5,
+    -10,  -5,  -5,  -2,  -2,  -5,  -5, -10
+];
+const QUEEN_ENDGAME: [i32; 64] = [
+    -10,  -5,  -5,  -2,  -2,  -5,  -5, -10,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+     -5,   0,   2,   5,   5,   2,   0,  -5,
+     -2,   0,   5,   7,   7,   5,   0,  -2,
+     -2,   0,   5,   7,   7,   5,   0,  -2,
+     -5,   0,   2,   5,   5,   2,   0,  -5,
+     -5,   0,   0,   0,   0,   0,   0,  -5,
+    -10,  -5,  -5,  -2,  -2,  -5,  -5, -10
+];
+const KING_MIDDLEGAME: [i32; 64] = [
+    -15, -20, -20, -25, -25, -20, -20, -15,
+    -15, -20, -20, -25, -25, -20, -20, -15,
+    -15, -20, -20, -25, -25, -20, -20, -15,
+    -15, -20, -20, -25, -25, -20, -20, -15,
+    -10, -15, -15, -20, -20, -15, -15, -10,
+     -5, -10, -10, -10, -10, -10, -10,  -5,
+     10,  10,   0,   0,   0,   0,  10,  10,
+     10,  15,   5,   0,   0,   5,  15,  10
+];
+const KING_ENDGAME: [i32; 64] = [
+    -25, -20, -15, -10, -10, -15, -20, -25,
+    -15, -10,  -5,   0,   0,  -5, -10, -15,
+    -15,  -5,  10,  15,  15,  10,  -5, -15,
+    -15,  -5,  15,  20,  20,  15,  -5, -15,
+    -15,  -5,  15,  20,  20,  15,  -5, -15,
+    -15,  -5,  10,  15,  15,  10,  -5, -15,
+    -15, -15,   0,   0,   0,   0, -15, -15,
+    -25, -15, -15, -15, -15, -15, -15, -25
+];
+
+/// Bonus for each piece on each square, once for the middlegame and once for the endgame.
+/// Tables are indexed by `ChessPiece as usize`, squares by `y * 8 + x` from white's side.
+#[derive(Clone, Debug)]
+pub struct PieceSquareTables {
+    pub middlegame: [[i32; 64]; 6],
+    pub endgame: [[i32; 64]; 6],
+    /// How much each piece counts towards the middlegame, see `MAX_PHASE`.
+    pub phase_weights: [i32; 6]
+}
+
+impl Default for PieceSquareTables {
+    fn default() -> PieceSquareTables {
+        PieceSquareTables {
+            middlegame: [KING_MIDDLEGAME, QUEEN_MIDDLEGAME, BISHOP_MIDDLEGAME, PAWN_MIDDLEGAME, KNIGHT_MIDDLEGAME, ROOK_MIDDLEGAME],
+            endgame: [KING_ENDGAME, QUEEN_ENDGAME, BISHOP_ENDGAME, PAWN_ENDGAME, KNIGHT_ENDGAME, ROOK_ENDGAME],
+            phase_weights: PHASE_WEIGHTS
+        }
+    }
+}
+
+/// A middlegame and an endgame value, blended by the game phase.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Tapered {
+    pub middlegame: i32,
+    pub endgame: i32
+}
+
+impl Tapered {
+    pub const fn new(middlegame: i32, endgame: i32) -> Tapered {
+        Tapered { middlegame, endgame }
+    }
+
+    pub fn taper(self, phase: i32) -> i32 {
+        taper(self.middlegame, self.endgame, phase)
+    }
+}
+
+impl Add for Tapered {
+    type Output = Tapered;
+
+    fn add(self, other: Tapered) -> Tapered {
+        Tapered::new(self.middlegame + other.middlegame, self.endgame + other.endgame)
+    }
+}
+
+impl AddAssign for Tapered {
+    fn add_assign(&mut self, other: Tapered) {
+        *self = *self + other;
+    }
+}
+
+impl Mul<i32> for Tapered {
+    type Output = Tapered;
+
+    fn mul(self, factor: i32) -> Tapered {
+        Tapered::new(self.middlegame * factor, self.endgame * factor)
+    }
+}
+
+/// Weights of the evaluation terms on top of material.
+#[derive(Clone, Debug, Default)]
+pub struct EvalParams {
+    pub piece_squares: PieceSquareTables,
+    pub pawns: PawnParams,
+    pub king_safety: KingSafetyParams,
+    pub activity: ActivityParams
+}
+
+fn table_index(side: Side, (x, y): (i8, i8)) -> usize {
+    let row = match side {
+        Side::White => y,
+        Side::Black => 7 - y
+    };
+    (row * 8 + x) as usize
+}
+
+impl PieceSquareTables {
+    /// `MAX_PHASE` with every piece still on the board, 0 once only kings and pawns are left.
+    pub fn phase(&self, pieces: &[(ChessPiece, Side, (i8, i8))]) -> i32 {
+        pieces.iter()
+            .map(|(piece, _, _)| self.phase_weights[*piece as usize])
+            .sum::<i32>()
+            .min(MAX_PHASE)
+    }
+
+    /// White's piece-square bonus minus black's, the middlegame and endgame values
+    /// blended by `phase`.
+    pub fn score(&self, pieces: &[(ChessPiece, Side, (i8, i8))], phase: i32) -> i32 {
+        let (middlegame, endgame) = pieces.iter()
+            .fold((0, 0), |(middlegame, endgame), (piece, side, square)| {
+                let index = table_index(*side, *square);
+                let sign = *side as i32;
+                (middlegame + sign * self.middlegame[*piece as usize][index],
+                 endgame + sign * self.endgame[*piece as usize][index])
+            });
+        taper(middlegame, endgame, phase)
+    }
+}
+
+/// Blends a middlegame and an endgame value, `phase` runs from `MAX_PHASE` down to 0.
+pub fn taper(middlegame: i32, endgame: i32, phase: i32) -> i32 {
+    (middlegame * phase + endgame * (MAX_PHASE - phase)) / MAX_PHASE
+}
+
+/// Material plus the positional terms, from whose_turn's point of view.
+/// Pawn structure is looked up in `pawns` before it is worked out.
+pub fn evaluate(board: &Board, whose_turn: Side, params: &EvalParams, pawns: &mut PawnTable) -> i32 {
+    let material = Board::evaluate_board_score(board.get_board_state(), whose_turn);
+    let pieces = board.pieces();
+    let phase = params.piece_squares.phase(&pieces);
+    let tapered = pawns.probe(board, &pieces, &params.pawns)
+        + king_safety::king_safety(board, &pieces, &params.king_safety)
+        + activity::piece_activity(board, &pieces, &params.activity);
+    let positional = params.piece_squares.score(&pieces, phase) + tapered.taper(phase);
+    material + positional * whose_turn as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap_turn;
+
+    //Same position seen from the other side: ranks flipped and colours swapped
+    fn colour_mirrored(pieces: &[(ChessPiece, Side, (i8, i8))]) -> Vec<(ChessPiece, Side, (i8, i8))> {
+        pieces.iter()
+            .map(|(piece, side, (x, y))| (*piece, swap_turn(*side), (*x, 7 - *y)))
+            .collect()
+    }
+
+    fn eval(pieces: &[(ChessPiece, Side, (i8, i8))], whose_turn: Side) -> i32 {
+        evaluate(&Board::from_pieces(pieces), whose_turn, &EvalParams::default(), &mut PawnTable::new(16))
+    }
+
+    #[test]
+    fn full_board_is_pure_middlegame_and_bare_kings_pure_endgame() {
+        let tables = PieceSquareTables::default();
+        assert_eq!(tables.phase(&Board::start_pieces()), MAX_PHASE);
+        //Extra material cannot push the phase past the middlegame
+        let mut extra_queens = Board::start_pieces();
+        extra_queens.push((ChessPiece::Queen, Side::White, (3, 4)));
+        assert_eq!(tables.phase(&extra_queens), MAX_PHASE);
+
+        let kings = [(ChessPiece::King, Side::White, (3, 7)), (ChessPiece::King, Side::Black, (4, 1))];
+        assert_eq!(tables.phase(&kings), 0);
+        //Only pawns left still counts as a pure endgame
+        let mut kings_and_pawns = kings.to_vec();
+        kings_and_pawns.push((ChessPiece::Pawn, Side::White, (0, 6)));
+        assert_eq!(tables.phase(&kings_and_pawns), 0);
+
+        let term = Tapered::new(30, -10);
+        assert_eq!(term.taper(MAX_PHASE), 30);
+        assert_eq!(term.taper(0), -10);
+        assert_eq!(term.taper(MAX_PHASE / 2), 10);
+
+        //Bare kings only see the endgame table, black's from its own side of the board
+        let expected = KING_ENDGAME[7 * 8 + 3] - KING_ENDGAME[(7 - 1) * 8 + 4];
+        assert_eq!(tables.score(&kings, 0), expected);
+    }
+
+    #[test]
+    fn colour_mirrored_position_scores_the_other_way_round() {
+        let pieces = vec![
+            (ChessPiece::King, Side::White, (1, 7)),
+            (ChessPiece::Queen, Side::White, (4, 4)),
+            (ChessPiece::Rook, Side::White, (3, 7)),
+            (ChessPiece::Knight, Side::White, (2, 3)),
+            (ChessPiece::Pawn, Side::White, (0, 6)),
+            (ChessPiece::Pawn, Side::White, (1, 6)),
+            (ChessPiece::Pawn, Side::White, (2, 4)),
+            (ChessPiece::Pawn, Side::White, (5, 5)),
+            (ChessPiece::King, Side::Black, (6, 0)),
+            (ChessPiece::Rook, Side::Black, (0, 0)),
+            (ChessPiece::Bishop, Side::Black, (5, 2)),
+            (ChessPiece::Bishop, Side::Black, (2, 0)),
+            (ChessPiece::Pawn, Side::Black, (5, 1)),
+            (ChessPiece::Pawn, Side::Black, (6, 1)),
+            (ChessPiece::Pawn, Side::Black, (7, 2))
+        ];
+        let mirrored = colour_mirrored(&pieces);
+
+        let score = eval(&pieces, Side::White);
+        assert_ne!(score, 0);
+        assert_eq!(eval(&mirrored, Side::White), -score);
+        assert_eq!(eval(&mirrored, Side::Black), score);
+        assert_eq!(eval(&pieces, Side::Black), -score);
+
+        //The piece-square tables alone mirror the same way
+        let tables = PieceSquareTables::default();
+        let phase = tables.phase(&pieces);
+        assert_eq!(tables.phase(&mirrored), phase);
+        assert_eq!(tables.score(&mirrored, phase), -tables.score(&pieces, phase));
+    }
+}
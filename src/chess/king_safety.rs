@@ -0,0 +1,234 @@
+use crate::{Side, Board, swap_turn};
+use super::evaluation::Tapered;
+use super::pawns::forward;
+use super::piece::ChessPiece;
+
+//Danger of the attack units on the king zone, rising faster the more pieces join in
+const SAFETY_TABLE: [i32; 24] = [
+    0, 0, 2, 4, 8, 12, 18, 24, 32, 40, 50, 60,
+    72, 84, 98, 112, 128, 144, 162, 180, 200, 220, 242, 264
+];
+
+/// Weights of the king-safety terms. The safety terms only have a middlegame value so they
+/// fade out as pieces come off, while the activity terms only count in the endgame.
+#[derive(Clone, Debug)]
+pub struct KingSafetyParams {
+    /// For a pawn of the king's side one and two squares in front of it, on its own or a
+    /// neighbouring file.
+    pub shield: [Tapered; 2],
+    /// For the closest enemy pawn on those files, by how many squares in front of the king it is.
+    pub storm: [Tapered; 8],
+    /// For one of those files without a pawn of the king's side.
+    pub half_open_file: Tapered,
+    /// Replaces `half_open_file` when the file has no pawns at all.
+    pub open_file: Tapered,
+    /// Attack units every enemy piece reaching the king zone adds, in ChessPiece order.
+    pub attack_weights: [usize; 6],
+    /// Penalty by attack units, the last entry is used for anything beyond.
+    pub safety_table: Vec<i32>,
+    /// For every square the king can move to.
+    pub king_mobility: Tapered,
+    /// For every square between the king and the closest pawn.
+    pub pawn_distance: Tapered
+}
+
+impl Default for KingSafetyParams {
+    fn default() -> KingSafetyParams {
+        KingSafetyParams {
+            shield: [Tapered::new(8, 0), Tapered::new(4, 0)],
+            storm: [
+                Tapered::new(0, 0), Tapered::new(-6, 0), Tapered::new(-10, 0), Tapered::new(-6, 0),
+                Tapered::new(-3, 0), Tapered::new(0, 0), Tapered::new(0, 0), Tapered::new(0, 0)
+            ],
+            half_open_file: Tapered::new(-8, 0),
+            open_file: Tapered::new(-12, 0),
+            attack_weights: [0, 5, 2, 0, 2, 3],
+            safety_table: SAFETY_TABLE.to_vec(),
+            king_mobility: Tapered::new(0, 2),
+            pawn_distance: Tapered::new(0, -2)
+        }
+    }
+}
+
+impl KingSafetyParams {
+    /// No weight on any term and no attack units.
+    #[cfg(test)]
+    pub fn zero() -> KingSafetyParams {
+        KingSafetyParams {
+            shield: [Tapered::default(); 2],
+            storm: [Tapered::default(); 8],
+            half_open_file: Tapered::default(),
+            open_file: Tapered::default(),
+            attack_weights: [0; 6],
+            safety_table: vec![0],
+            king_mobility: Tapered::default(),
+            pawn_distance: Tapered::default()
+        }
+    }
+}
+
+//The king's square, those around it and the three two squares further towards the enemy
+fn king_zone(king: (i8, i8), side: Side) -> Vec<(i8, i8)> {
+    let ahead = 2 * forward(side);
+    (-1..=1)
+        .flat_map(|dx| (-1..=1).map(move |dy| (king.0 + dx, king.1 + dy)))
+        .chain((-1..=1).map(|dx| (king.0 + dx, king.1 + ahead)))
+        .filter(|(x, y)| (0..8).contains(x) && (0..8).contains(y))
+        .collect()
+}
+
+//Shield, storm and open files on the king's file and the two beside it
+fn pawn_cover(board: &Board, king: (i8, i8), side: Side, params: &KingSafetyParams) -> Tapered {
+    let step = forward(side);
+    let mut score = Tapered::default();
+    for file in (king.0 - 1..=king.0 + 1).filter(|file| (0..8).contains(file)) {
+        let pawn_at = |y: i8| match board.piece_on((file, y)) {
+            Some((ChessPiece::Pawn, pawn_side)) => Some(pawn_side),
+            _ => None
+        };
+        for (distance, shield) in params.shield.iter().enumerate() {
+            if pawn_at(king.1 + step * (distance as i8 + 1)) == Some(side) {score += *shield;}
+        }
+
+        let closest_storm = (1..8)
+            .find(|distance| pawn_at(king.1 + step * distance) == Some(swap_turn(side)));
+        if let Some(distance) = closest_storm {score += params.storm[distance as usize];}
+
+        let pawns: Vec<Side> = (0..8).filter_map(pawn_at).collect();
+        if pawns.is_empty() {
+            score += params.open_file;
+        } else if !pawns.contains(&side) {
+            score += params.half_open_file;
+        }
+    }
+    score
+}
+
+//Danger from the enemy pieces that can reach the king zone
+fn attacks(board: &Board, pieces: &[(ChessPiece, Side, (i8, i8))], king: (i8, i8), side: Side, params: &KingSafetyParams) -> Tapered {
+    let zone = king_zone(king, side);
+    let units: usize = pieces.iter()
+        .filter(|(piece, piece_side, _)| *piece_side != side && params.attack_weights[*piece as usize] > 0)
+        .filter(|(piece, piece_side, square)| {
+            ChessPiece::get_pseudolegal_moves(board.get_board_state(), *square, piece, piece_side).iter()
+                .any(|to| zone.contains(to))
+        })
+        .map(|(piece, _, _)| params.attack_weights[*piece as usize])
+        .sum();
+    let danger = match params.safety_table.get(units) {
+        Some(danger) => *danger,
+        None => params.safety_table.last().copied().unwrap_or(0)
+    };
+    Tapered::new(-danger, 0)
+}
+
+//Endgame kings belong near the pawns with room to move
+fn activity(board: &Board, pieces: &[(ChessPiece, Side, (i8, i8))], king: (i8, i8), side: Side, params: &KingSafetyParams) -> Tapered {
+    let mobility = ChessPiece::get_pseudolegal_moves(board.get_board_state(), king, &ChessPiece::King, &side).len() as i32;
+    let pawn_distance = pieces.iter()
+        .filter(|(piece, _, _)| *piece == ChessPiece::Pawn)
+        .map(|(_, _, (x, y))| (x - king.0).abs().max((y - king.1).abs()) as i32)
+        .min()
+        .unwrap_or(0);
+    params.king_mobility * mobility + params.pawn_distance * pawn_distance
+}
+
+/// King safety and activity for both kings, white minus black.
+pub fn king_safety(board: &Board, pieces: &[(ChessPiece, Side, (i8, i8))], params: &KingSafetyParams) -> Tapered {
+    let mut score = Tapered::default();
+    for (_, side, king) in pieces.iter().filter(|(piece, _, _)| *piece == ChessPiece::King) {
+        let king_score = pawn_cover(board, *king, *side, params)
+            + attacks(board, pieces, *king, *side, params)
+            + activity(board, pieces, *king, *side, params);
+        score += king_score * *side as i32;
+    }
+    score
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Both kings on their start squares
+    const WHITE_KING: (i8, i8) = (3, 7);
+    const BLACK_KING: (i8, i8) = (3, 0);
+
+    fn safety(board: &Board, params: &KingSafetyParams) -> Tapered {
+        king_safety(board, &board.pieces(), params)
+    }
+
+    #[test]
+    fn pawns_in_front_of_the_king_shield_it() {
+        let params = KingSafetyParams { shield: [Tapered::new(8, 0), Tapered::new(4, 0)], ..KingSafetyParams::zero() };
+        //Two pawns right in front of the king and one a square further on the next file
+        let board = Board::with_kings(WHITE_KING, BLACK_KING, &[
+            (ChessPiece::Pawn, Side::White, (2, 6)),
+            (ChessPiece::Pawn, Side::White, (3, 6)),
+            (ChessPiece::Pawn, Side::White, (4, 5)),
+            //Too far away from the king's files
+            (ChessPiece::Pawn, Side::White, (6, 6))
+        ]);
+        assert_eq!(safety(&board, &params), Tapered::new(20, 0));
+    }
+
+    #[test]
+    fn closest_enemy_pawn_on_each_file_storms() {
+        let storm = [0, -1, -2, -3, -4, -5, -6, -7].map(|weight| Tapered::new(weight, 0));
+        let params = KingSafetyParams { storm, ..KingSafetyParams::zero() };
+        let board = Board::with_kings(WHITE_KING, BLACK_KING, &[
+            (ChessPiece::Pawn, Side::Black, (4, 5)),
+            //Only the closer of the two pawns on x = 2 counts
+            (ChessPiece::Pawn, Side::Black, (2, 4)),
+            (ChessPiece::Pawn, Side::Black, (2, 1))
+        ]);
+        assert_eq!(safety(&board, &params), Tapered::new(-5, 0));
+    }
+
+    #[test]
+    fn files_without_own_pawns_are_open_or_half_open() {
+        let params = KingSafetyParams {
+            half_open_file: Tapered::new(-8, 0),
+            open_file: Tapered::new(-12, 0),
+            ..KingSafetyParams::zero()
+        };
+        //White: x = 3 and 4 only have black pawns. Black: x = 2 only has a white pawn
+        let board = Board::with_kings(WHITE_KING, BLACK_KING, &[
+            (ChessPiece::Pawn, Side::White, (2, 6)),
+            (ChessPiece::Pawn, Side::Black, (3, 2)),
+            (ChessPiece::Pawn, Side::Black, (4, 1))
+        ]);
+        assert_eq!(safety(&board, &params), Tapered::new(-8, 0));
+
+        //Without pawns every file of both kings is open, which cancels out
+        assert_eq!(safety(&Board::with_kings(WHITE_KING, BLACK_KING, &[]), &params), Tapered::default());
+    }
+
+    #[test]
+    fn attackers_of_the_king_zone_add_up_in_the_safety_table() {
+        let params = KingSafetyParams {
+            attack_weights: KingSafetyParams::default().attack_weights,
+            safety_table: SAFETY_TABLE.to_vec(),
+            ..KingSafetyParams::zero()
+        };
+        //Queen 5 units and knight 2, both reach (4, 6) next to the white king
+        let queen = (ChessPiece::Queen, Side::Black, (4, 5));
+        let knight = (ChessPiece::Knight, Side::Black, (2, 5));
+        assert_eq!(safety(&Board::with_kings(WHITE_KING, BLACK_KING, &[queen]), &params), Tapered::new(-SAFETY_TABLE[5], 0));
+        assert_eq!(safety(&Board::with_kings(WHITE_KING, BLACK_KING, &[queen, knight]), &params), Tapered::new(-SAFETY_TABLE[7], 0));
+
+        //A knight that cannot reach the zone adds nothing
+        let far_knight = (ChessPiece::Knight, Side::Black, (7, 2));
+        assert_eq!(safety(&Board::with_kings(WHITE_KING, BLACK_KING, &[queen, far_knight]), &params), Tapered::new(-SAFETY_TABLE[5], 0));
+    }
+
+    #[test]
+    fn attack_units_beyond_the_table_use_its_last_entry() {
+        let params = KingSafetyParams {
+            attack_weights: KingSafetyParams::default().attack_weights,
+            safety_table: vec![0, 10, 30],
+            ..KingSafetyParams::zero()
+        };
+        let board = Board::with_kings(WHITE_KING, BLACK_KING, &[(ChessPiece::Queen, Side::Black, (4, 5))]);
+        assert_eq!(safety(&board, &params), Tapered::new(-30, 0));
+    }
+}
@@ -0,0 +1,284 @@
+use std::f64::consts::SQRT_2;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::{Side, Board, swap_turn};
+use super::board::Move;
+use super::evaluation::{self, EvalParams};
+use super::pawns::PawnTable;
+use super::minimax::{PvLine, SearchInfo, SearchLimits, SearchResult};
+use super::score::Score;
+
+//Iterations between looking at the limits and reporting progress
+const REPORT_INTERVAL: u64 = 1024;
+//Four pawns, the evaluation lead that makes a win about 73% likely
+const EVAL_SCALE: f64 = 200.0;
+
+/// Tunable knobs for the tree search.
+#[derive(Clone, Debug)]
+pub struct MctsParams {
+    /// UCT exploration constant, higher spreads the visits over more moves.
+    pub exploration: f64,
+    /// Iteration budget, `SearchLimits::nodes` can only lower it.
+    pub iterations: u64,
+    /// Random plies played from a new leaf before it is evaluated, 0 evaluates the leaf itself.
+    pub rollout_depth: usize,
+    /// Seeds expansion order and rollouts, the same seed and budget build the same tree.
+    pub seed: u64,
+    /// Scores the position a rollout stops in.
+    pub eval: EvalParams
+}
+
+impl Default for MctsParams {
+    fn default() -> MctsParams {
+        MctsParams {
+            exploration: SQRT_2,
+            iterations: 50_000,
+            rollout_depth: 0,
+            seed: 0,
+            eval: EvalParams::default()
+        }
+    }
+}
+
+struct Node {
+    //Move leading here, the root has none
+    l_move: Option<Move>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    //Legal moves that have no child yet
+    untried: Vec<Move>,
+    whose_turn: Side,
+    depth: usize,
+    visits: u32,
+    //Summed results for the side that played `l_move`
+    wins: f64
+}
+
+struct Tree<'a> {
+    board: &'a mut Board,
+    params: &'a MctsParams,
+    nodes: Vec<Node>,
+    seldepth: usize,
+    rng: StdRng,
+    pawns: PawnTable
+}
+
+//SearchInfo counts plies in a byte, longer lines are reported at its limit
+fn saturated_plies(plies: usize) -> u8 {
+    u8::try_from(plies).unwrap_or(u8::MAX)
+}
+
+fn win_probability(score: i32) -> f64 {
+    1.0 / (1.0 + (-score as f64 / EVAL_SCALE).exp())
+}
+
+fn win_rate_to_score(win_rate: f64) -> Score {
+    let win_rate = win_rate.clamp(0.001, 0.999);
+    Score((EVAL_SCALE * (win_rate / (1.0 - win_rate)).ln()) as i32)
+}
+
+impl<'a> Tree<'a> {
+    fn new(board: &'a mut Board, whose_turn: Side, params: &'a MctsParams) -> Tree<'a> {
+        let root = Node {
+            l_move: None,
+            parent: None,
+            children: vec![],
+            untried: board.get_legal_moves_for_side(whose_turn),
+            whose_turn,
+            depth: 0,
+            visits: 0,
+            wins: 0.0
+        };
+        Tree { board, params, nodes: vec![root], seldepth: 0, rng: StdRng::seed_from_u64(params.seed), pawns: PawnTable::default() }
+    }
+
+    //UCT, the win rate of the child plus a bonus for being visited little
+    fn select_child(&self, node: usize) -> usize {
+        let parent = &self.nodes[node];
+        let log_visits = (parent.visits as f64).ln();
+        let uct = |child: &usize| {
+            let child = &self.nodes[*child];
+            let visits = child.visits as f64;
+            child.wins / visits + self.params.exploration * (log_visits / visits).sqrt()
+        };
+        *parent.children.iter()
+            .max_by(|a, b| uct(a).total_cmp(&uct(b)))
+            .expect("selection only descends into expanded nodes")
+    }
+
+    //Board is already in the position after `l_move`
+    fn add_child(&mut self, parent: usize, l_move: Move) -> usize {
+        let whose_turn = swap_turn(self.nodes[parent].whose_turn);
+        let depth = self.nodes[parent].depth + 1;
+        self.seldepth = self.seldepth.max(depth);
+        self.nodes.push(Node {
+            l_move: Some(l_move),
+            parent: Some(parent),
+            children: vec![],
+            untried: self.board.get_legal_moves_for_side(whose_turn),
+            whose_turn,
+            depth,
+            visits: 0,
+            wins: 0.0
+        });
+        let child = self.nodes.len() - 1;
+        self.nodes[parent].children.push(child);
+        child
+    }
+
+    //Result for the side to move at the leaf, 1 is a win and 0 a loss
+    fn simulate(&mut self, whose_turn: Side) -> f64 {
+        let mut side = whose_turn;
+        let mut played: Vec<Move> = vec![];
+        let result = loop {
+            let mut moves = self.board.get_legal_moves_for_side(side);
+            if moves.is_empty() {
+                break if self.board.is_in_check(side) {0.0} else {0.5};
+            }
+            if played.len() == self.params.rollout_depth {
+                break win_probability(evaluation::evaluate(self.board, side, &self.params.eval, &mut self.pawns));
+            }
+            let l_move = moves.swap_remove(self.rng.gen_range(0..moves.len()));
+            self.board.make_move(&l_move);
+            played.push(l_move);
+            side = swap_turn(side);
+        };
+        for l_move in played.iter().rev() {
+            self.board.unmake_move(l_move);
+        }
+        if side == whose_turn {result} else {1.0 - result}
+    }
+
+    fn iterate(&mut self) {
+        let mut path: Vec<Move> = vec![];
+        let mut node = 0;
+
+        while self.nodes[node].untried.is_empty() && !self.nodes[node].children.is_empty() {
+            node = self.select_child(node);
+            let l_move = self.nodes[node].l_move.clone().expect("only the root has no move");
+            self.board.make_move(&l_move);
+            path.push(l_move);
+        }
+
+        //Terminal nodes have nothing left to expand and are simulated as they are
+        if !self.nodes[node].untried.is_empty() {
+            let untried = &mut self.nodes[node].untried;
+            let l_move = untried.swap_remove(self.rng.gen_range(0..untried.len()));
+            self.board.make_move(&l_move);
+            node = self.add_child(node, l_move.clone());
+            path.push(l_move);
+        }
+
+        let mut result = self.simulate(self.nodes[node].whose_turn);
+        loop {
+            let current = &mut self.nodes[node];
+            current.visits += 1;
+            //Nodes score for the side that moved into them
+            current.wins += 1.0 - result;
+            result = 1.0 - result;
+            match current.parent {
+                Some(parent) => node = parent,
+                None => break
+            }
+        }
+
+        for l_move in path.iter().rev() {
+            self.board.unmake_move(l_move);
+        }
+    }
+
+    fn most_visited_child(&self, node: usize) -> Option<usize> {
+        self.nodes[node].children.iter()
+            .copied()
+            .max_by_key(|child| self.nodes[*child].visits)
+    }
+
+    fn info(&self, iterations: u64, started: Instant) -> SearchInfo {
+        let mut pv: Vec<Move> = vec![];
+        let mut node = 0;
+        while let Some(child) = self.most_visited_child(node) {
+            pv.push(self.nodes[child].l_move.clone().expect("only the root has no move"));
+            node = child;
+        }
+
+        let score = match self.most_visited_child(0) {
+            Some(best) => win_rate_to_score(self.nodes[best].wins / self.nodes[best].visits as f64),
+            None => Score::default()
+        };
+        let elapsed = started.elapsed();
+        SearchInfo {
+            depth: saturated_plies(pv.len()),
+            seldepth: saturated_plies(self.seldepth),
+            nodes: iterations,
+            nps: (iterations as f64 / elapsed.as_secs_f64().max(0.001)) as u64,
+            score,
+            lines: vec![PvLine { score, pv: pv.clone() }],
+            pv,
+            elapsed,
+            ..Default::default()
+        }
+    }
+}
+
+/// Monte Carlo tree search: UCT selection, expansion of one new move per iteration,
+/// a rollout or evaluation of the new leaf and backpropagation of the result.
+/// Runs until the iteration budget, `limits.nodes`, `limits.movetime` or `limits.stop`
+/// ends it, `limits.depth` is not used. The most visited move is returned.
+pub fn search(whose_turn: Side, board: &Board, params: &MctsParams, limits: &SearchLimits, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+    let started = Instant::now();
+    let mut board = board.clone();
+    let mut tree = Tree::new(&mut board, whose_turn, params);
+    if tree.nodes[0].untried.is_empty() {return None;}
+
+    let clock = limits.start_clock(started);
+    let mut iterations: u64 = 0;
+    loop {
+        tree.iterate();
+        iterations += 1;
+        if iterations >= params.iterations || limits.budget_spent(iterations, &clock) {break;}
+        if iterations.is_multiple_of(REPORT_INTERVAL) {
+            if limits.stop.load(Ordering::Relaxed) {break;}
+            on_iteration(&tree.info(iterations, started));
+        }
+    }
+
+    let info = tree.info(iterations, started);
+    Some(SearchResult { best_move: info.pv[0].clone(), info, trace: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_positions::back_rank_mate;
+
+    #[test]
+    fn finds_a_back_rank_mate_in_one() {
+        let params = MctsParams { iterations: 5_000, seed: 3, ..Default::default() };
+        let result = search(Side::White, &back_rank_mate(), &params, &SearchLimits::default(), &mut |_| {}).unwrap();
+        assert_eq!(result.best_move, Move { from: (0, 7), to: (0, 0) });
+        assert_eq!(result.info.nodes, 5_000);
+    }
+
+    #[test]
+    fn every_iteration_is_counted_once_at_the_root() {
+        let mut board = back_rank_mate();
+        let params = MctsParams { rollout_depth: 4, seed: 11, ..Default::default() };
+        let mut tree = Tree::new(&mut board, Side::White, &params);
+        for _ in 0..2_000 {
+            tree.iterate();
+        }
+        let root = &tree.nodes[0];
+        assert_eq!(root.visits, 2_000);
+        let child_visits: u32 = root.children.iter().map(|child| tree.nodes[*child].visits).sum();
+        assert_eq!(child_visits, 2_000);
+        //Every node below the root got one visit when it was added and one for each visit to a child
+        for node in tree.nodes.iter().skip(1).filter(|node| !node.children.is_empty()) {
+            let child_visits: u32 = node.children.iter().map(|child| tree.nodes[*child].visits).sum();
+            assert_eq!(node.visits, child_visits + 1);
+        }
+    }
+}
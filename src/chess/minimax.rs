@@ -1,17 +1,224 @@
-use macroquad::rand;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
 use crate::{Side, Board, swap_turn};
-use super::board::{Cell, Move};
-use super::{board::BoardPiece, piece::ChessPiece};
+use super::board::Move;
+use super::evaluation::{self, EvalParams};
+use super::pawns::PawnTable;
+use super::piece::ChessPiece;
+use super::see::static_exchange;
+use super::score::{self, Score, MATE_BOUND, TB_WIN};
+use super::tablebase::{Tablebases, Wdl};
+use super::trace::{SearchTrace, TraceBuilder, TraceEvent, TraceKind};
+use super::transposition::{Bound, TableEntry, TranspositionTable};
+use super::zobrist;
+
+const INFINITY: i32 = i32::MAX / 2;
+//Nodes between looking at the limits
+const CHECK_INTERVAL: u64 = 256;
+/// Extensions are counted in fractions of a ply, this many make a whole one.
+pub const ONE_PLY: i32 = 4;
 
 struct MoveRating {
     from: (i8, i8),
     to: (i8, i8),
-    rating: i32
+    rating: i32,
+    pv: Vec<Move>
+}
+
+/// One root move with its score and continuation.
+#[derive(Clone, Debug)]
+pub struct PvLine {
+    pub score: Score,
+    pub pv: Vec<Move>
+}
+
+/// Progress of a search, reported after every completed iteration.
+#[derive(Clone, Debug, Default)]
+pub struct SearchInfo {
+    pub depth: u8,
+    /// Deepest ply reached, quiescence included.
+    pub seldepth: u8,
+    /// Nodes over all threads, quiescence nodes included.
+    pub nodes: u64,
+    pub qnodes: u64,
+    pub nps: u64,
+    /// Transposition table usage in permille.
+    pub hashfull: u32,
+    /// From the point of view of the side that searched.
+    pub score: Score,
+    /// Principal variation, starting with the best move.
+    pub pv: Vec<Move>,
+    /// Best `multi_pv` root moves, best first. The first line matches `score` and `pv`.
+    pub lines: Vec<PvLine>,
+    pub elapsed: Duration
+}
+
+/// Outcome of a search, `info` describes the last completed iteration.
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    pub best_move: Move,
+    pub info: SearchInfo,
+    /// Tree of the last completed iteration when `SearchParams::trace_depth` asked for one.
+    pub trace: Option<SearchTrace>
+}
+
+/// When a search has to give up. Whatever ends it first wins, and the best move found
+/// up to that point is returned.
+#[derive(Clone, Debug)]
+pub struct SearchLimits {
+    pub depth: u8,
+    pub nodes: Option<u64>,
+    pub movetime: Option<Duration>,
+    /// Shareable so front-ends can stop a search running on another thread.
+    pub stop: Arc<AtomicBool>,
+    /// Raised for a search on the opponent's time. Node and time limits wait until it is
+    /// lowered on a ponder hit, `movetime` then counts from that moment.
+    pub ponder: Arc<AtomicBool>
+}
+
+impl Default for SearchLimits {
+    fn default() -> SearchLimits {
+        SearchLimits {
+            depth: 10,
+            nodes: None,
+            movetime: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            ponder: Arc::new(AtomicBool::new(false))
+        }
+    }
+}
+
+impl SearchLimits {
+    /// When the move's clock started, set at the ponder hit for a ponder search.
+    pub fn start_clock(&self, started: Instant) -> OnceLock<Instant> {
+        let clock = OnceLock::new();
+        if !self.ponder.load(Ordering::Relaxed) {
+            let _ = clock.set(started);
+        }
+        clock
+    }
+
+    /// Whether the node or time limit is hit, neither applies while pondering.
+    pub fn budget_spent(&self, nodes: u64, clock: &OnceLock<Instant>) -> bool {
+        if self.ponder.load(Ordering::Relaxed) {return false;}
+        let clock_started = clock.get_or_init(Instant::now);
+        self.nodes.is_some_and(|limit| nodes >= limit)
+            || self.movetime.is_some_and(|movetime| clock_started.elapsed() >= movetime)
+    }
+}
+
+/// Tunable knobs for the search.
+#[derive(Clone, Debug)]
+pub struct SearchParams {
+    pub null_move_pruning: bool,
+    /// Extra plies taken off the null-move search on top of the skipped move.
+    pub null_move_reduction: i32,
+    pub null_move_min_depth: i32,
+    pub late_move_reductions: bool,
+    pub lmr_min_depth: i32,
+    /// Moves searched at full depth before quiet moves start getting reduced.
+    pub lmr_full_depth_moves: usize,
+    pub lmr_reduction: i32,
+    /// Null-window nodes this shallow return the static evaluation once it beats beta
+    /// by `reverse_futility_margin` per ply of depth.
+    pub reverse_futility_pruning: bool,
+    pub reverse_futility_depth: i32,
+    pub reverse_futility_margin: i32,
+    /// Quiet moves are skipped this close to the leaves when the static evaluation plus
+    /// `futility_margin` per ply still cannot reach alpha.
+    pub futility_pruning: bool,
+    pub futility_depth: i32,
+    pub futility_margin: i32,
+    /// Nodes whose static evaluation trails alpha by more than `razoring_margin` per ply
+    /// drop into quiescence, and return its score if that confirms the fail low.
+    pub razoring: bool,
+    pub razoring_depth: i32,
+    pub razoring_margin: i32,
+    /// Quiet moves past the first `lmp_base_moves` plus depth squared are skipped this close to the leaves.
+    pub late_move_pruning: bool,
+    pub lmp_depth: i32,
+    pub lmp_base_moves: usize,
+    /// Half-width of the first aspiration window around the previous iteration's score.
+    pub aspiration_window: i32,
+    pub aspiration_min_depth: i32,
+    /// Extensions in `ONE_PLY` units for moves that give check, for the only legal way
+    /// out of check and for pawns stepping onto the rank before the last.
+    pub check_extension: i32,
+    pub single_reply_extension: i32,
+    pub pawn_push_extension: i32,
+    /// Most extension a single line can gather, in `ONE_PLY` units.
+    pub max_extension: i32,
+    /// Lazy SMP worker count, the search is deterministic with a single thread.
+    pub threads: usize,
+    pub hash_size_mb: usize,
+    /// Seeds the tie-breaking between equal root moves, a single-threaded search
+    /// with the same seed and limits plays the same move.
+    pub seed: u64,
+    /// Number of best root moves to search and report, each with its own line.
+    pub multi_pv: usize,
+    pub eval: EvalParams,
+    /// What a draw costs the side that searches, positive avoids draws against a weaker
+    /// opponent and negative settles for them against a stronger one.
+    pub contempt: i32,
+    /// Endgame tables, probed inside the search and used to pick the move at the root.
+    pub tablebases: Option<Arc<Tablebases>>,
+    /// Records the main thread's tree this many plies deep, see `SearchResult::trace`.
+    pub trace_depth: Option<usize>
+}
+
+impl Default for SearchParams {
+    fn default() -> SearchParams {
+        SearchParams {
+            null_move_pruning: true,
+            null_move_reduction: 2,
+            null_move_min_depth: 3,
+            late_move_reductions: true,
+            lmr_min_depth: 3,
+            lmr_full_depth_moves: 4,
+            lmr_reduction: 1,
+            reverse_futility_pruning: true,
+            reverse_futility_depth: 3,
+            reverse_futility_margin: 60,
+            futility_pruning: true,
+            futility_depth: 2,
+            futility_margin: 75,
+            razoring: true,
+            razoring_depth: 2,
+            razoring_margin: 150,
+            late_move_pruning: true,
+            lmp_depth: 3,
+            lmp_base_moves: 3,
+            aspiration_window: 50,
+            aspiration_min_depth: 4,
+            check_extension: ONE_PLY,
+            single_reply_extension: ONE_PLY,
+            pawn_push_extension: ONE_PLY / 2,
+            max_extension: 4 * ONE_PLY,
+            threads: 1,
+            hash_size_mb: 16,
+            seed: 0,
+            multi_pv: 1,
+            eval: EvalParams::default(),
+            contempt: 0,
+            tablebases: None,
+            trace_depth: None
+        }
+    }
+}
+
+//What a draw is worth to whose_turn, contempt is what it costs the side the search is for
+fn draw_score(contempt: i32, whose_turn: Side, root_side: Side) -> i32 {
+    if whose_turn == root_side {-contempt} else {contempt}
 }
 
-fn best_move(mut moves: Vec<MoveRating>, whose_turn: Side) -> MoveRating {
-    let random = rand::gen_range(0, moves.len());
+fn best_move(mut moves: Vec<MoveRating>, whose_turn: Side, rng: &mut StdRng) -> MoveRating {
+    let random = rng.gen_range(0..moves.len());
     let mut best_move_rating: MoveRating = moves.remove(random);
     let best_move = match whose_turn {
         //Max = 1
@@ -28,68 +235,1114 @@ fn best_move(mut moves: Vec<MoveRating>, whose_turn: Side) -> MoveRating {
     best_move_rating
 }
 
-static mut EVALUATED_MOVES: i32 = 0;
-static mut MINIMAX_CALLS: i32 = 0;
+//Captures first, most valuable victim by least valuable attacker.
+//Captures that lose material in the exchange go after the quiet moves
+fn order_moves(board: &Board, mut moves: Vec<Move>) -> Vec<Move> {
+    moves.sort_by_cached_key(|l_move| {
+        match board.captured_piece(l_move) {
+            Some(victim) => {
+                let exchange = static_exchange(board, l_move);
+                if exchange < 0 {return -exchange;}
+                let attacker = Board::get_piece(board.get_board_state(), l_move.from).unwrap_or(ChessPiece::Pawn);
+                ChessPiece::get_material_price(&attacker) - ChessPiece::get_material_price(&victim) * 8
+            },
+            None => 0
+        }
+    });
+    moves
+}
+
+//Node counts summed over every thread
+#[derive(Default)]
+struct SearchCounters {
+    nodes: AtomicU64,
+    qnodes: AtomicU64
+}
+
+//What a single Lazy SMP thread shares with the others
+#[derive(Clone, Copy)]
+struct ThreadContext<'a> {
+    table: &'a TranspositionTable,
+    counters: &'a SearchCounters,
+    started: Instant,
+    clock: &'a OnceLock<Instant>,
+    limits: &'a SearchLimits,
+    //Raised by the main thread once it is done, helpers unwind as soon as they see it
+    helpers_stop: &'a AtomicBool,
+    //Helpers run slightly ahead of the main thread
+    depth_offset: i32,
+    //Every thread breaks ties with its own generator
+    seed: u64,
+    //Only the main thread records the tree
+    traced: bool
+}
+
+struct Search<'a> {
+    board: &'a mut Board,
+    params: &'a SearchParams,
+    context: ThreadContext<'a>,
+    stopped: bool,
+    nodes: u64,
+    qnodes: u64,
+    //Part of nodes/qnodes already added to the shared counters
+    flushed_nodes: u64,
+    flushed_qnodes: u64,
+    seldepth: usize,
+    //Principal variation of the previous iteration, tried first at each ply
+    previous_pv: Vec<Move>,
+    ply: usize,
+    //Side the search is for, contempt is from its point of view
+    root_side: Side,
+    //Extension gathered by the moves leading to the current position, in ONE_PLY units
+    line_extension: i32,
+    rng: StdRng,
+    pawns: PawnTable,
+    trace: Option<TraceBuilder>
+}
+
+impl<'a> Search<'a> {
+    fn new(board: &'a mut Board, params: &'a SearchParams, context: ThreadContext<'a>, root_side: Side) -> Search<'a> {
+        Search {
+            board,
+            params,
+            context,
+            stopped: false,
+            nodes: 0,
+            qnodes: 0,
+            flushed_nodes: 0,
+            flushed_qnodes: 0,
+            seldepth: 0,
+            previous_pv: vec![],
+            ply: 0,
+            root_side,
+            line_extension: 0,
+            rng: StdRng::seed_from_u64(context.seed),
+            pawns: PawnTable::default(),
+            trace: params.trace_depth.filter(|_| context.traced).map(TraceBuilder::new)
+        }
+    }
+
+    //Score from whose_turn's point of view
+    fn evaluate(&mut self, whose_turn: Side) -> i32 {
+        evaluation::evaluate(self.board, whose_turn, &self.params.eval, &mut self.pawns)
+    }
 
-fn evaluate_move(l_move: &Move, board: &Board, whose_turn: Side) -> MoveRating {
-    unsafe {
-        EVALUATED_MOVES += 1;
+    fn make_move(&mut self, l_move: &Move) {
+        self.board.make_move(l_move);
+        self.ply += 1;
+        if let Some(trace) = &mut self.trace {trace.make_move(Some(l_move));}
     }
-    MoveRating {
-        from: l_move.from,
-        to: l_move.to,
-        rating: Board::evaluate_board_score(board.get_board_state(), whose_turn) * -1
+
+    fn unmake_move(&mut self, l_move: &Move) {
+        self.board.unmake_move(l_move);
+        self.ply -= 1;
+        if let Some(trace) = &mut self.trace {trace.unmake_move();}
+    }
+
+    fn flush_counters(&mut self) {
+        self.context.counters.nodes.fetch_add(self.nodes - self.flushed_nodes, Ordering::Relaxed);
+        self.context.counters.qnodes.fetch_add(self.qnodes - self.flushed_qnodes, Ordering::Relaxed);
+        self.flushed_nodes = self.nodes;
+        self.flushed_qnodes = self.qnodes;
+    }
+
+    fn limit_reached(&self) -> bool {
+        let limits = self.context.limits;
+        limits.stop.load(Ordering::Relaxed)
+            || self.context.helpers_stop.load(Ordering::Relaxed)
+            || limits.budget_spent(self.context.counters.nodes.load(Ordering::Relaxed), self.context.clock)
+    }
+
+    fn draw_score(&self, whose_turn: Side) -> i32 {
+        draw_score(self.params.contempt, whose_turn, self.root_side)
+    }
+
+    //Exact score once the position is in the tablebases, nearer wins score higher
+    fn probe_tablebases(&self, whose_turn: Side) -> Option<i32> {
+        let tablebases = self.params.tablebases.as_ref()?;
+        if self.board.piece_count() > tablebases.max_pieces() {return None;}
+        let wdl = tablebases.probe_wdl(self.board, whose_turn)?;
+        Some(match wdl {
+            Wdl::Win => TB_WIN - self.ply as i32,
+            Wdl::Draw => self.draw_score(whose_turn),
+            Wdl::Loss => -TB_WIN + self.ply as i32
+        })
+    }
+
+    fn trace_enter(&mut self, kind: TraceKind, depth: i32, alpha: i32, beta: i32) {
+        if let Some(trace) = &mut self.trace {trace.enter(kind, depth, alpha, beta);}
+    }
+
+    fn trace_leave(&mut self, score: i32) {
+        if let Some(trace) = &mut self.trace {trace.leave(score);}
+    }
+
+    fn trace_event(&mut self, event: TraceEvent) {
+        if let Some(trace) = &mut self.trace {trace.event(event);}
+    }
+
+    //Counts the node and every CHECK_INTERVAL nodes publishes the counts and looks at the limits
+    fn visit_node(&mut self, quiescence: bool) -> bool {
+        self.nodes += 1;
+        if quiescence {self.qnodes += 1;}
+        self.seldepth = self.seldepth.max(self.ply);
+
+        if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.flush_counters();
+            if self.limit_reached() {
+                self.stopped = true;
+            }
+        }
+        self.stopped
+    }
+
+    //Hash move first, then the previous iteration's PV move, then captures
+    fn ordered_moves(&self, whose_turn: Side, table_move: Option<Move>) -> Vec<Move> {
+        let mut moves = order_moves(self.board, self.board.get_all_moves_for_side(whose_turn));
+        let preferred = [self.previous_pv.get(self.ply).cloned(), table_move];
+        for preferred_move in preferred.into_iter().flatten() {
+            if let Some(index) = moves.iter().position(|l_move| *l_move == preferred_move) {
+                let preferred_move = moves.remove(index);
+                moves.insert(0, preferred_move);
+            }
+        }
+        moves
+    }
+
+    fn quiescence(&mut self, alpha: i32, beta: i32, whose_turn: Side) -> i32 {
+        self.trace_enter(TraceKind::Quiescence, 0, alpha, beta);
+        let score = self.quiescence_node(alpha, beta, whose_turn);
+        self.trace_leave(score);
+        score
+    }
+
+    fn quiescence_node(&mut self, mut alpha: i32, beta: i32, whose_turn: Side) -> i32 {
+        if self.visit_node(true) {return 0;}
+        let stand_pat = self.evaluate(whose_turn);
+        if stand_pat >= beta {
+            self.trace_event(TraceEvent::StandPat);
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        //Captures that lose material in the exchange cannot raise the stand pat score
+        let (captures, losing_captures): (Vec<Move>, Vec<Move>) = self.board.get_all_moves_for_side(whose_turn).into_iter()
+            .filter(|l_move| self.board.captured_piece(l_move).is_some())
+            .partition(|l_move| static_exchange(self.board, l_move) >= 0);
+        if !losing_captures.is_empty() {
+            self.trace_event(TraceEvent::LosingCapturesSkipped { count: losing_captures.len() });
+        }
+
+        let mut best_score = stand_pat;
+        for (move_index, capture) in order_moves(self.board, captures).into_iter().enumerate() {
+            self.make_move(&capture);
+            if self.board.is_in_check(whose_turn) {
+                self.unmake_move(&capture);
+                continue;
+            }
+            let score = -self.quiescence(-beta, -alpha, swap_turn(whose_turn));
+            self.unmake_move(&capture);
+            if self.stopped {return 0;}
+
+            if score > best_score {best_score = score;}
+            if score >= beta {
+                self.trace_event(TraceEvent::BetaCutoff { move_index });
+                break;
+            }
+            alpha = alpha.max(score);
+        }
+        best_score
+    }
+
+    //Extension units the move just made earns, whole plies only come from adding them up along the line
+    fn extension_units(&self, l_move: &Move, whose_turn: Side, gives_check: bool, single_reply: bool) -> i32 {
+        let seventh_rank = match whose_turn {
+            Side::White => 1,
+            Side::Black => 6
+        };
+        let pawn_push = l_move.to.1 == seventh_rank
+            && self.board.piece_on(l_move.to).is_some_and(|(piece, _)| piece == ChessPiece::Pawn);
+
+        let mut units = 0;
+        if gives_check {units += self.params.check_extension;}
+        if single_reply {units += self.params.single_reply_extension;}
+        if pawn_push {units += self.params.pawn_push_extension;}
+        units
+    }
+
+    //Adds the units to the line, up to max_extension, and returns the whole plies that
+    //crossed along with the units actually added, which are taken back once the move is undone
+    fn extend_line(&mut self, units: i32) -> (i32, i32) {
+        let granted = units.min(self.params.max_extension - self.line_extension).max(0);
+        let plies = (self.line_extension + granted) / ONE_PLY - self.line_extension / ONE_PLY;
+        self.line_extension += granted;
+        (plies, granted)
+    }
+
+    //Only worth counting when in check, elsewhere a single legal move hardly ever happens
+    fn has_single_reply(&mut self, moves: &[Move], whose_turn: Side) -> bool {
+        let mut legal_moves = 0;
+        for l_move in moves {
+            self.board.make_move(l_move);
+            if !self.board.is_in_check(whose_turn) {legal_moves += 1;}
+            self.board.unmake_move(l_move);
+            if legal_moves > 1 {return false;}
+        }
+        legal_moves == 1
+    }
+
+    //Static evaluation far enough above beta that a shallow search is not expected to drop below it
+    fn can_prune_reverse_futility(&self, depth: i32, beta: i32, static_eval: i32) -> bool {
+        self.params.reverse_futility_pruning
+            && depth <= self.params.reverse_futility_depth
+            && beta.abs() < MATE_BOUND
+            && static_eval - self.params.reverse_futility_margin * depth >= beta
+    }
+
+    fn can_razor(&self, depth: i32, alpha: i32, static_eval: i32) -> bool {
+        self.params.razoring
+            && depth <= self.params.razoring_depth
+            && alpha.abs() < MATE_BOUND
+            && static_eval + self.params.razoring_margin * depth < alpha
+    }
+
+    //Whether quiet moves at this node cannot lift the score to alpha
+    fn is_futile(&self, depth: i32, alpha: i32, static_eval: i32) -> bool {
+        self.params.futility_pruning
+            && depth <= self.params.futility_depth
+            && alpha.abs() < MATE_BOUND
+            && static_eval + self.params.futility_margin * depth <= alpha
+    }
+
+    fn is_late_move(&self, depth: i32, move_index: usize) -> bool {
+        self.params.late_move_pruning
+            && depth <= self.params.lmp_depth
+            && move_index >= self.params.lmp_base_moves + (depth * depth) as usize
+    }
+
+    fn can_try_null_move(&self, depth: i32, beta: i32, whose_turn: Side, in_check: bool) -> bool {
+        //Passing is only safe when zugzwang is unlikely
+        self.params.null_move_pruning
+            && depth >= self.params.null_move_min_depth
+            && beta < INFINITY
+            && !in_check
+            && self.board.has_non_pawn_material(whose_turn)
+    }
+
+    //Principal variation search, pv is filled in when the score lands inside the window
+    fn alpha_beta(&mut self, depth: i32, alpha: i32, beta: i32, whose_turn: Side, allow_null: bool, pv: &mut Vec<Move>) -> i32 {
+        pv.clear();
+        if depth <= 0 {return self.quiescence(alpha, beta, whose_turn);}
+        self.trace_enter(TraceKind::Search, depth, alpha, beta);
+        let score = self.alpha_beta_node(depth, alpha, beta, whose_turn, allow_null, pv);
+        self.trace_leave(score);
+        score
+    }
+
+    fn alpha_beta_node(&mut self, depth: i32, mut alpha: i32, beta: i32, whose_turn: Side, allow_null: bool, pv: &mut Vec<Move>) -> i32 {
+        if self.visit_node(false) {return 0;}
+        //Whoever could repeat the position can keep repeating it
+        if self.board.is_repetition() {
+            self.trace_event(TraceEvent::Repetition);
+            return self.draw_score(whose_turn);
+        }
+        if let Some(score) = self.probe_tablebases(whose_turn) {
+            self.trace_event(TraceEvent::TablebaseHit);
+            return score;
+        }
+
+        let key = self.board.hash() ^ zobrist::side_key(whose_turn);
+        let mut table_move: Option<Move> = None;
+        if let Some(entry) = self.context.table.probe(key) {
+            //Only null-window nodes take cutoffs, PV nodes keep searching so the line stays intact
+            let entry_score = score::from_table(entry.score, self.ply);
+            let cutoff = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry_score >= beta,
+                Bound::Upper => entry_score <= alpha
+            };
+            if beta - alpha == 1 && entry.depth >= depth && cutoff {
+                self.trace_event(TraceEvent::TableCutoff);
+                return entry_score;
+            }
+            table_move = entry.best_move;
+        }
+        let original_alpha = alpha;
+
+        let swapped_turn = swap_turn(whose_turn);
+        let in_check = self.board.is_in_check(whose_turn);
+        //Forward pruning trusts the static evaluation, which says little in check or on the PV
+        let static_eval = if in_check || beta - alpha > 1 {None} else {Some(self.evaluate(whose_turn))};
+
+        if let Some(static_eval) = static_eval {
+            if self.can_prune_reverse_futility(depth, beta, static_eval) {
+                self.trace_event(TraceEvent::ReverseFutility);
+                return static_eval;
+            }
+            if self.can_razor(depth, alpha, static_eval) {
+                let score = self.quiescence(alpha, alpha + 1, whose_turn);
+                if self.stopped {return 0;}
+                if score <= alpha {
+                    self.trace_event(TraceEvent::Razored);
+                    return score;
+                }
+            }
+        }
+        let futile = static_eval.is_some_and(|static_eval| self.is_futile(depth, alpha, static_eval));
+
+        if allow_null && self.can_try_null_move(depth, beta, whose_turn, in_check) {
+            let null_depth = depth - 1 - self.params.null_move_reduction;
+            self.board.make_null_move();
+            self.ply += 1;
+            if let Some(trace) = &mut self.trace {trace.make_move(None);}
+            let score = -self.alpha_beta(null_depth, -beta, -beta + 1, swapped_turn, false, &mut vec![]);
+            if let Some(trace) = &mut self.trace {trace.unmake_move();}
+            self.ply -= 1;
+            self.board.unmake_null_move();
+            if self.stopped {return 0;}
+            //A mate found after passing proves nothing about the real position
+            if score >= beta {
+                self.trace_event(TraceEvent::NullMoveCutoff);
+                return if score >= MATE_BOUND {beta} else {score};
+            }
+        }
+
+        let moves = self.ordered_moves(whose_turn, table_move);
+        let single_reply = in_check && self.has_single_reply(&moves, whose_turn);
+
+        let mut best_score = -INFINITY;
+        let mut best_move: Option<Move> = None;
+        let mut child_pv: Vec<Move> = vec![];
+        let mut move_index = 0;
+        for possible_move in moves.iter() {
+            let is_capture = self.board.captured_piece(possible_move).is_some();
+            self.make_move(possible_move);
+            if self.board.is_in_check(whose_turn) {
+                self.unmake_move(possible_move);
+                continue;
+            }
+
+            let gives_check = self.board.is_in_check(swapped_turn);
+            let units = self.extension_units(possible_move, whose_turn, gives_check, single_reply);
+            let (extension, granted) = self.extend_line(units);
+            if extension > 0 {
+                self.trace_event(TraceEvent::Extended { l_move: possible_move.clone(), plies: extension });
+            }
+            let child_depth = depth - 1 + extension;
+
+            let quiet = !is_capture && !gives_check && extension == 0;
+            if move_index > 0 && quiet && static_eval.is_some() && (futile || self.is_late_move(depth, move_index)) {
+                let event = if futile {
+                    TraceEvent::FutilityPruned { l_move: possible_move.clone() }
+                } else {
+                    TraceEvent::LateMovePruned { l_move: possible_move.clone() }
+                };
+                self.trace_event(event);
+                self.line_extension -= granted;
+                self.unmake_move(possible_move);
+                move_index += 1;
+                continue;
+            }
+
+            let score = if move_index == 0 {
+                -self.alpha_beta(child_depth, -beta, -alpha, swapped_turn, true, &mut child_pv)
+            } else {
+                let reduce = self.params.late_move_reductions
+                    && depth >= self.params.lmr_min_depth
+                    && move_index >= self.params.lmr_full_depth_moves
+                    && !is_capture
+                    && !in_check
+                    && !gives_check
+                    && extension == 0;
+                let reduction = if reduce {self.params.lmr_reduction} else {0};
+                if reduce {
+                    self.trace_event(TraceEvent::Reduced { l_move: possible_move.clone(), reduction });
+                }
+
+                let mut score = -self.alpha_beta(child_depth - reduction, -alpha - 1, -alpha, swapped_turn, true, &mut child_pv);
+                //Reduced search failed high, verify at full depth before trusting it
+                if score > alpha && reduction > 0 {
+                    self.trace_event(TraceEvent::ReSearched { l_move: possible_move.clone() });
+                    score = -self.alpha_beta(child_depth, -alpha - 1, -alpha, swapped_turn, true, &mut child_pv);
+                }
+                if score > alpha && score < beta {
+                    self.trace_event(TraceEvent::ReSearched { l_move: possible_move.clone() });
+                    score = -self.alpha_beta(child_depth, -beta, -alpha, swapped_turn, true, &mut child_pv);
+                }
+                score
+            };
+            self.line_extension -= granted;
+            self.unmake_move(possible_move);
+            if self.stopped {return 0;}
+            move_index += 1;
+
+            if score > best_score {
+                best_score = score;
+                best_move = Some(possible_move.clone());
+            }
+            if score >= beta {
+                self.trace_event(TraceEvent::BetaCutoff { move_index: move_index - 1 });
+                break;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(possible_move.clone());
+                pv.append(&mut child_pv);
+            }
+        }
+
+        //No legal move, checkmate or stalemate
+        if move_index == 0 {
+            self.trace_event(if in_check {TraceEvent::Checkmate} else {TraceEvent::Stalemate});
+            return if in_check {Score::mated_in(self.ply).0} else {self.draw_score(whose_turn)};
+        }
+
+        let bound = if best_score >= beta {
+            Bound::Lower
+        } else if best_score > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.context.table.store(key, TableEntry { best_move, score: score::to_table(best_score, self.ply), depth, bound });
+        best_score
+    }
+
+    //Returns the best score found along with every root move's rating.
+    //Moves in excluded already have a line of their own in MultiPV mode
+    fn root(&mut self, depth: i32, alpha: i32, beta: i32, whose_turn: Side, excluded: &[Move]) -> (i32, Vec<MoveRating>) {
+        let mut moves = self.ordered_moves(whose_turn, None);
+        moves.retain(|l_move| !excluded.contains(l_move));
+        let swapped_turn = swap_turn(whose_turn);
+        self.trace_enter(TraceKind::Root, depth, alpha, beta);
+
+        let mut rated_moves: Vec<MoveRating> = vec![];
+        let mut best_score = -INFINITY;
+        for possible_move in moves {
+            let move_index = rated_moves.len();
+            self.make_move(&possible_move);
+            if self.board.is_in_check(whose_turn) {
+                self.unmake_move(&possible_move);
+                continue;
+            }
+            let mut child_pv: Vec<Move> = vec![];
+            let gives_check = self.board.is_in_check(swapped_turn);
+            let units = self.extension_units(&possible_move, whose_turn, gives_check, false);
+            let (extension, granted) = self.extend_line(units);
+            if extension > 0 {
+                self.trace_event(TraceEvent::Extended { l_move: possible_move.clone(), plies: extension });
+            }
+            let child_depth = depth - 1 + extension;
+
+            let score = if move_index == 0 {
+                -self.alpha_beta(child_depth, -beta, -alpha, swapped_turn, true, &mut child_pv)
+            } else {
+                //Null window just below the best score so that equally good moves get re-searched
+                //and come back with exact scores for the tie-break in best_move
+                let floor = best_score.max(alpha);
+                let score = -self.alpha_beta(child_depth, -floor, -floor + 1, swapped_turn, true, &mut child_pv);
+                if score >= floor && score < beta {
+                    self.trace_event(TraceEvent::ReSearched { l_move: possible_move.clone() });
+                    -self.alpha_beta(child_depth, -beta, -(floor - 1), swapped_turn, true, &mut child_pv)
+                } else {
+                    score
+                }
+            };
+            self.line_extension -= granted;
+            self.unmake_move(&possible_move);
+            if self.stopped {break;}
+
+            best_score = best_score.max(score);
+            let mut pv = vec![possible_move.clone()];
+            pv.append(&mut child_pv);
+            rated_moves.push(MoveRating {
+                from: possible_move.from,
+                to: possible_move.to,
+                rating: score * whose_turn as i32,
+                pv
+            });
+            if score >= beta {
+                self.trace_event(TraceEvent::BetaCutoff { move_index });
+                break;
+            }
+        }
+        self.trace_leave(best_score);
+        (best_score, rated_moves)
     }
 }
 
-fn minimax(depth: u8, l_move: &Move, whose_turn: Side, board: &mut Board) -> MoveRating {
-    unsafe {
-        MINIMAX_CALLS += 1;
+enum LineOutcome {
+    Found(MoveRating),
+    NoMoves,
+    //Best move among the root moves finished before the cut, if it can be trusted
+    Stopped(Option<MoveRating>)
+}
+
+impl<'a> Search<'a> {
+    //Later lines can come back above earlier ones when the search is unstable, so they are put
+    //in order again, ties keeping the order they were found in
+    fn iteration_result(&self, mut lines: Vec<MoveRating>, depth: i32, whose_turn: Side) -> SearchResult {
+        lines.sort_by_key(|line| -line.rating * whose_turn as i32);
+        let lines: Vec<(Move, PvLine)> = lines.into_iter()
+            .map(|line| (Move {from: line.from, to: line.to}, PvLine {score: Score(line.rating * whose_turn as i32), pv: line.pv}))
+            .collect();
+        let (best_move, best_line) = lines[0].clone();
+        let mut info = SearchInfo {
+            depth: depth as u8,
+            seldepth: self.seldepth as u8,
+            hashfull: self.context.table.hashfull(),
+            score: best_line.score,
+            pv: best_line.pv,
+            lines: lines.into_iter().map(|(_, line)| line).collect(),
+            ..Default::default()
+        };
+        self.context.counters.fill_in(&mut info, self.context.started);
+        SearchResult { best_move, info, trace: None }
     }
-    if depth == 0 {return evaluate_move(l_move, board, whose_turn);}
 
-    let all_moves_for_side = board.get_all_moves_for_side(whose_turn);
+    //Cut off before anything was searched, any move beats resigning on the spot
+    fn fallback_result(&mut self, whose_turn: Side) -> Option<SearchResult> {
+        let first_move = self.board.get_legal_moves_for_side(whose_turn).into_iter().next()?;
+        let mut info = SearchInfo {
+            pv: vec![first_move.clone()],
+            ..Default::default()
+        };
+        self.context.counters.fill_in(&mut info, self.context.started);
+        Some(SearchResult { best_move: first_move, info, trace: None })
+    }
 
-    let swapped_turn = swap_turn(whose_turn);
-    let mut rated_moves: Vec<MoveRating> = vec![];
-    for possible_move in all_moves_for_side {
-        board.make_move(&possible_move);
-        let best_move = minimax(depth - 1, &possible_move, swapped_turn, board);
-        board.unmake_move(&possible_move);
-        rated_moves.push(MoveRating {
-            from: possible_move.from,
-            to: possible_move.to,
-            rating: best_move.rating
-        });
+    //Starts from a narrow window around the line's score in the previous iteration
+    //and widens it whenever the root fails low or high
+    fn aspiration_search(&mut self, depth: i32, whose_turn: Side, excluded: &[Move], previous_score: Option<i32>) -> LineOutcome {
+        let mut delta = self.params.aspiration_window;
+        let (mut alpha, mut beta) = match previous_score {
+            Some(score) if depth >= self.params.aspiration_min_depth => (score - delta, score + delta),
+            _ => (-INFINITY, INFINITY)
+        };
+
+        loop {
+            let (best_score, rated_moves) = self.root(depth, alpha, beta, whose_turn, excluded);
+            self.flush_counters();
+            if self.stopped {
+                //The root moves finished so far were searched a ply deeper than the last
+                //iteration, and the previous best move always goes first among them
+                if !rated_moves.is_empty() && best_score > alpha && best_score < beta {
+                    return LineOutcome::Stopped(Some(best_move(rated_moves, whose_turn, &mut self.rng)));
+                }
+                return LineOutcome::Stopped(None);
+            }
+            if rated_moves.is_empty() {return LineOutcome::NoMoves;}
+
+            if best_score <= alpha && alpha > -INFINITY {
+                delta *= 2;
+                alpha = (best_score - delta).max(-INFINITY);
+                continue;
+            }
+            if best_score >= beta && beta < INFINITY {
+                delta *= 2;
+                beta = (best_score + delta).min(INFINITY);
+                continue;
+            }
+            return LineOutcome::Found(best_move(rated_moves, whose_turn, &mut self.rng));
+        }
     }
-    if rated_moves.len() == 0 {return MoveRating {from: (0, 0), to: (0, 0), rating: 0};}
-    best_move(rated_moves, whose_turn)
 }
 
-pub fn opponents_turn(board: &mut Board, mut simulated_board: Board, whose_turn: Side) -> Side {
-    unsafe {
-        EVALUATED_MOVES = 0;
-        MINIMAX_CALLS = 0;
+fn minimax(whose_turn: Side, board: &mut Board, params: &SearchParams, context: ThreadContext, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+    let mut search = Search::new(board, params, context, whose_turn);
+    let result = deepen(&mut search, whose_turn, on_iteration);
+    let trace = search.trace.take().and_then(TraceBuilder::finish);
+    result.map(|result| SearchResult { trace, ..result })
+}
+
+//Iterative deepening. With MultiPV each iteration searches the root once per line,
+//leaving out the moves that already got a line
+fn deepen(search: &mut Search, whose_turn: Side, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+    let params = search.params;
+    let depth_offset = search.context.depth_offset;
+    let max_depth = search.context.limits.depth as i32;
+    let mut result: Option<SearchResult> = None;
+
+    for depth in (1 + depth_offset)..=(max_depth + depth_offset) {
+        if let Some(trace) = &mut search.trace {trace.start_iteration(depth);}
+        let mut lines: Vec<MoveRating> = vec![];
+        for line_index in 0..params.multi_pv.max(1) {
+            let excluded: Vec<Move> = lines.iter()
+                .map(|line| Move {from: line.from, to: line.to})
+                .collect();
+            let previous_score = result.as_ref()
+                .and_then(|previous| previous.info.lines.get(line_index))
+                .map(|line| line.score.0);
+
+            match search.aspiration_search(depth, whose_turn, &excluded, previous_score) {
+                LineOutcome::Found(line) => lines.push(line),
+                LineOutcome::NoMoves => break,
+                LineOutcome::Stopped(partial) => {
+                    //Lines completed at this depth are still worth more than the last iteration
+                    let completed_depth = if lines.is_empty() {
+                        lines.extend(partial);
+                        depth - 1
+                    } else {
+                        depth
+                    };
+                    if !lines.is_empty() {
+                        result = Some(search.iteration_result(lines, completed_depth, whose_turn));
+                    }
+                    return result.or_else(|| search.fallback_result(whose_turn));
+                }
+            }
+        }
+        if lines.is_empty() {return None;}
+
+        if let Some(trace) = &mut search.trace {trace.finish_iteration();}
+        let iteration = search.iteration_result(lines, depth, whose_turn);
+        search.previous_pv = iteration.info.pv.clone();
+        on_iteration(&iteration.info);
+        result = Some(iteration);
     }
+    result
+}
+
+impl SearchCounters {
+    fn fill_in(&self, info: &mut SearchInfo, started: Instant) {
+        info.nodes = self.nodes.load(Ordering::Relaxed);
+        info.qnodes = self.qnodes.load(Ordering::Relaxed);
+        info.elapsed = started.elapsed();
+        info.nps = (info.nodes as f64 / info.elapsed.as_secs_f64().max(0.001)) as u64;
+    }
+}
 
-    let mut rated_moves: Vec<MoveRating> = vec![];
-    let blank_move = Move {
-        from: (0, 0),
-        to: (0, 0)
+//Root already in the tablebases, the DTZ-optimal move needs no search
+fn tablebase_result(tablebases: &Tablebases, board: &Board, whose_turn: Side, contempt: i32) -> Option<SearchResult> {
+    let started = Instant::now();
+    let (best_move, probe) = tablebases.best_root_move(board, whose_turn)?;
+    let score = Score(match probe.wdl {
+        Wdl::Win => TB_WIN,
+        Wdl::Draw => draw_score(contempt, whose_turn, whose_turn),
+        Wdl::Loss => -TB_WIN
+    });
+    let pv = vec![best_move.clone()];
+    let info = SearchInfo {
+        depth: 1,
+        seldepth: 1,
+        score,
+        pv: pv.clone(),
+        lines: vec![PvLine { score, pv }],
+        elapsed: started.elapsed(),
+        ..Default::default()
     };
-    let last_move = std::mem::replace(&mut board.last_move, blank_move);
+    Some(SearchResult { best_move, info, trace: None })
+}
+
+/// Lazy SMP: every thread searches the same root on its own copy of the board and they only
+/// cooperate through the shared transposition table. The main thread's result is returned.
+/// Once a limit is hit the best move found so far is returned.
+/// `on_iteration` is called on the calling thread after every completed iteration.
+pub fn search(whose_turn: Side, board: &Board, params: &SearchParams, table: &TranspositionTable, limits: &SearchLimits, on_iteration: &mut dyn FnMut(&SearchInfo)) -> Option<SearchResult> {
+    let tablebase_move = params.tablebases.as_ref()
+        .filter(|tablebases| board.piece_count() <= tablebases.max_pieces())
+        .and_then(|tablebases| tablebase_result(tablebases, board, whose_turn, params.contempt));
+    if let Some(result) = tablebase_move {
+        on_iteration(&result.info);
+        return Some(result);
+    }
+
+    let helpers_stop = AtomicBool::new(false);
+    let counters = SearchCounters::default();
+    let started = Instant::now();
+    let clock = limits.start_clock(started);
+    let context = ThreadContext {
+        table,
+        counters: &counters,
+        started,
+        clock: &clock,
+        limits,
+        helpers_stop: &helpers_stop,
+        depth_offset: 0,
+        seed: params.seed,
+        traced: params.trace_depth.is_some()
+    };
+
+    let result = thread::scope(|scope| {
+        for thread_index in 1..params.threads {
+            let mut helper_board = board.clone();
+            let helper_context = ThreadContext {
+                depth_offset: (thread_index % 2) as i32,
+                seed: params.seed.wrapping_add(thread_index as u64),
+                traced: false,
+                ..context
+            };
+            scope.spawn(move || {
+                minimax(whose_turn, &mut helper_board, params, helper_context, &mut |_| {})
+            });
+        }
+
+        let mut main_board = board.clone();
+        let result = minimax(whose_turn, &mut main_board, params, context, on_iteration);
+        helpers_stop.store(true, Ordering::Relaxed);
+        result
+    });
+
+    //Helpers have flushed their last nodes by now
+    result.map(|mut result| {
+        counters.fill_in(&mut result.info, context.started);
+        result
+    })
+}
+
+pub fn format_pv(pv: &[Move]) -> String {
+    pv.iter()
+        .map(|l_move| format!("({}, {})->({}, {})", l_move.from.0, l_move.from.1, l_move.to.0, l_move.to.1))
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn format_info(info: &SearchInfo) -> String {
+    let mut formatted = format!("depth {} seldepth {} score {} nodes {} qnodes {} nps {} hashfull {} time {}ms pv {}",
+            info.depth, info.seldepth, info.score.to_protocol_string(), info.nodes, info.qnodes, info.nps, info.hashfull,
+            info.elapsed.as_millis(), format_pv(&info.pv));
+    if info.lines.len() > 1 {
+        for (line_index, line) in info.lines.iter().enumerate() {
+            formatted += &format!("\nmultipv {} score {} pv {}", line_index + 1, line.score.to_protocol_string(), format_pv(&line.pv));
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::test_positions::{back_rank_mate, middlegame};
+    use super::super::trace::TraceNode;
+
+    fn without_extensions(params: &mut SearchParams) {
+        params.check_extension = 0;
+        params.single_reply_extension = 0;
+        params.pawn_push_extension = 0;
+    }
+
+    //Defaults, everything off and every switch off on its own
+    fn pruning_switches() -> Vec<SearchParams> {
+        let switches: [fn(&mut SearchParams); 8] = [
+            |params| params.null_move_pruning = false,
+            |params| params.late_move_reductions = false,
+            |params| params.reverse_futility_pruning = false,
+            |params| params.futility_pruning = false,
+            |params| params.razoring = false,
+            |params| params.late_move_pruning = false,
+            |params| params.aspiration_min_depth = i32::MAX,
+            without_extensions
+        ];
+        let mut all_off = SearchParams::default();
+        switches.iter().for_each(|switch_off| switch_off(&mut all_off));
+
+        let mut configurations = vec![SearchParams::default(), all_off];
+        for switch_off in switches {
+            let mut params = SearchParams::default();
+            switch_off(&mut params);
+            configurations.push(params);
+        }
+        configurations
+    }
+
+    fn run_search(board: &Board, whose_turn: Side, params: &SearchParams, depth: u8) -> Option<SearchResult> {
+        let table = TranspositionTable::new(1);
+        let limits = SearchLimits { depth, ..Default::default() };
+        search(whose_turn, board, params, &table, &limits, &mut |_| {})
+    }
+
+    //Hands `f` a single-threaded search of the position with a table of its own
+    fn with_search<R>(board: &mut Board, whose_turn: Side, params: &SearchParams, f: impl FnOnce(&mut Search) -> R) -> R {
+        let table = TranspositionTable::new(1);
+        let limits = SearchLimits::default();
+        let counters = SearchCounters::default();
+        let helpers_stop = AtomicBool::new(false);
+        let started = Instant::now();
+        let clock = limits.start_clock(started);
+        let context = ThreadContext {
+            table: &table,
+            counters: &counters,
+            started,
+            clock: &clock,
+            limits: &limits,
+            helpers_stop: &helpers_stop,
+            depth_offset: 0,
+            seed: params.seed,
+            traced: false
+        };
+        f(&mut Search::new(board, params, context, whose_turn))
+    }
+
+    //Score of a single node, searched the way the root searches its children
+    fn node_score(board: &mut Board, whose_turn: Side, params: &SearchParams, depth: i32) -> i32 {
+        with_search(board, whose_turn, params, |search| {
+            search.alpha_beta(depth, -INFINITY, INFINITY, whose_turn, true, &mut vec![])
+        })
+    }
 
-    let best_move = minimax(10, &last_move, whose_turn, &mut simulated_board);
-    unsafe {
-        println!("Evaluated_moves: {}", EVALUATED_MOVES);
-        println!("Minimax_calls: {}", MINIMAX_CALLS);
+    //Rating of the best root move after a single aspiration search at `depth`
+    fn aspiration_rating(board: &mut Board, whose_turn: Side, depth: i32, previous_score: Option<i32>) -> i32 {
+        with_search(board, whose_turn, &SearchParams::default(), |search| {
+            match search.aspiration_search(depth, whose_turn, &[], previous_score) {
+                LineOutcome::Found(line) => line.rating,
+                _ => panic!("the root has moves and the search has no limits")
+            }
+        })
     }
-    //let best_move = best_move(rated_moves, whose_turn);
 
-    println!("best_move, from: ({}, {}), to: ({}, {})", best_move.from.0, best_move.from.1, best_move.to.0, best_move.to.1);
-    board.move_piece(best_move.from, best_move.to, whose_turn);
-    
-    swap_turn(whose_turn)
-    //get_all_legal_moves_for_player();
-    //get the best step and play it.
+    #[test]
+    fn finds_a_back_rank_mate_in_one() {
+        let board = back_rank_mate();
+        for params in pruning_switches() {
+            let result = run_search(&board, Side::White, &params, 4).unwrap();
+            assert_eq!(result.best_move, Move { from: (0, 7), to: (0, 0) });
+            assert_eq!(result.info.score, Score(score::MATE - 1));
+        }
+    }
+
+    #[test]
+    fn finds_a_rook_ladder_mate_in_two() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (3, 7)),
+            (ChessPiece::Rook, Side::White, (0, 3)),
+            (ChessPiece::Rook, Side::White, (1, 4)),
+            (ChessPiece::King, Side::Black, (7, 0))
+        ]);
+        for params in pruning_switches() {
+            let result = run_search(&board, Side::White, &params, 5).unwrap();
+            assert_eq!(result.info.score.mate_distance(), Some(2));
+            assert_eq!(result.info.score, Score(score::MATE - 3));
+            //Several first moves cut the king off, whichever it is the line has to end in mate
+            let mut mated = board.clone();
+            assert_eq!(result.info.pv.len(), 3);
+            for l_move in &result.info.pv {
+                assert!(mated.make_move(l_move));
+            }
+            assert!(mated.is_in_check(Side::Black));
+            assert!(mated.get_legal_moves_for_side(Side::Black).is_empty());
+        }
+    }
+
+    #[test]
+    fn check_extension_sees_a_mate_one_ply_past_the_depth() {
+        //Rook checks along y = 1, the king has to step back to y = 0 and the other rook mates there
+        let mut board = Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (3, 7)),
+            (ChessPiece::Rook, Side::White, (0, 2)),
+            (ChessPiece::Rook, Side::White, (1, 5)),
+            (ChessPiece::King, Side::Black, (7, 1))
+        ]);
+        //Two plies end the line once the king steps back, both checks earn a ply so the mate and
+        //the mated king's missing replies are still searched
+        assert_eq!(node_score(&mut board, Side::White, &SearchParams::default(), 2), score::MATE - 3);
+
+        //Quiescence cannot tell a mate apart, so without extensions the mated node needs a ply of its own
+        let mut params = SearchParams::default();
+        without_extensions(&mut params);
+        for depth in 2..=3 {
+            assert!(node_score(&mut board, Side::White, &params, depth) < MATE_BOUND);
+        }
+        assert_eq!(node_score(&mut board, Side::White, &params, 4), score::MATE - 3);
+    }
+
+    fn is_legal(board: &Board, whose_turn: Side, l_move: &Move) -> bool {
+        board.clone().get_legal_moves_for_side(whose_turn).contains(l_move)
+    }
+
+    #[test]
+    fn single_thread_search_is_repeatable() {
+        let board = middlegame();
+        let params = SearchParams { threads: 1, seed: 7, ..Default::default() };
+        let first = run_search(&board, Side::White, &params, 4).unwrap();
+        let second = run_search(&board, Side::White, &params, 4).unwrap();
+        assert_eq!(first.best_move, second.best_move);
+        assert_eq!(first.info.score, second.info.score);
+        assert_eq!(first.info.pv, second.info.pv);
+        assert_eq!(first.info.nodes, second.info.nodes);
+    }
+
+    #[test]
+    fn helper_threads_do_not_change_a_forced_mate() {
+        let board = back_rank_mate();
+        let params = SearchParams { threads: 4, ..Default::default() };
+        let result = run_search(&board, Side::White, &params, 4).unwrap();
+        assert_eq!(result.best_move, Move { from: (0, 7), to: (0, 0) });
+        assert_eq!(result.info.score, Score(score::MATE - 1));
+    }
+
+    #[test]
+    fn stalemate_scores_as_a_draw() {
+        let mut board = Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (3, 7)),
+            (ChessPiece::Queen, Side::White, (5, 1)),
+            (ChessPiece::King, Side::Black, (7, 0))
+        ]);
+        assert!(!board.is_in_check(Side::Black));
+        assert!(board.get_legal_moves_for_side(Side::Black).is_empty());
+        for params in pruning_switches() {
+            for depth in 1..=3 {
+                assert_eq!(node_score(&mut board, Side::Black, &params, depth), 0);
+            }
+            //Nothing to play at the root
+            assert!(run_search(&board, Side::Black, &params, 3).is_none());
+        }
+    }
+
+    #[test]
+    fn aspiration_re_search_matches_a_full_window() {
+        let mut boards = [
+            //Quiet middlegame, the score is an evaluation
+            Board::from_pieces(&[
+                (ChessPiece::King, Side::White, (6, 7)),
+                (ChessPiece::Rook, Side::White, (3, 7)),
+                (ChessPiece::Knight, Side::White, (5, 5)),
+                (ChessPiece::Pawn, Side::White, (5, 6)),
+                (ChessPiece::Pawn, Side::White, (6, 6)),
+                (ChessPiece::Pawn, Side::White, (7, 6)),
+                (ChessPiece::King, Side::Black, (6, 0)),
+                (ChessPiece::Rook, Side::Black, (2, 0)),
+                (ChessPiece::Bishop, Side::Black, (4, 2)),
+                (ChessPiece::Pawn, Side::Black, (5, 1)),
+                (ChessPiece::Pawn, Side::Black, (6, 1)),
+                (ChessPiece::Pawn, Side::Black, (7, 1))
+            ]),
+            //Mate in two, far outside any window around an evaluation
+            Board::from_pieces(&[
+                (ChessPiece::King, Side::White, (3, 7)),
+                (ChessPiece::Rook, Side::White, (0, 3)),
+                (ChessPiece::Rook, Side::White, (1, 4)),
+                (ChessPiece::King, Side::Black, (7, 0))
+            ])
+        ];
+        let depth = SearchParams::default().aspiration_min_depth;
+        for board in &mut boards {
+            let full_window = aspiration_rating(board, Side::White, depth, None);
+            //A window far below the score fails high, one far above it fails low
+            for guess in [full_window - 1000, full_window + 1000] {
+                let re_searched = aspiration_rating(board, Side::White, depth, Some(guess));
+                assert_eq!(re_searched, full_window);
+            }
+        }
+    }
+
+    #[test]
+    fn node_limit_stops_the_search_near_the_limit() {
+        let board = middlegame();
+        let table = TranspositionTable::new(1);
+        let limits = SearchLimits { depth: 30, nodes: Some(20_000), ..Default::default() };
+        let result = search(Side::White, &board, &SearchParams::default(), &table, &limits, &mut |_| {}).unwrap();
+        //Limits are looked at every CHECK_INTERVAL nodes
+        assert!(result.info.nodes >= 20_000);
+        assert!(result.info.nodes < 20_000 + 2 * CHECK_INTERVAL);
+        assert!(is_legal(&board, Side::White, &result.best_move));
+    }
+
+    #[test]
+    fn stop_flag_ends_the_search_with_a_legal_move() {
+        let board = middlegame();
+        let table = TranspositionTable::new(1);
+        let limits = SearchLimits { depth: 30, ..Default::default() };
+        let stop = limits.stop.clone();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let started = Instant::now();
+        let result = search(Side::White, &board, &SearchParams::default(), &table, &limits, &mut |_| {}).unwrap();
+        stopper.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(is_legal(&board, Side::White, &result.best_move));
+    }
+
+    #[test]
+    fn multi_pv_returns_distinct_root_moves_best_first() {
+        let board = middlegame();
+        let params = SearchParams { multi_pv: 3, ..Default::default() };
+        let result = run_search(&board, Side::White, &params, 3).unwrap();
+        let lines = &result.info.lines;
+        assert_eq!(lines.len(), 3);
+
+        let first_moves: Vec<&Move> = lines.iter().map(|line| &line.pv[0]).collect();
+        for (index, l_move) in first_moves.iter().enumerate() {
+            assert!(is_legal(&board, Side::White, l_move));
+            assert!(!first_moves[..index].contains(l_move));
+        }
+        assert!(lines.windows(2).all(|pair| pair[0].score >= pair[1].score));
+        assert_eq!(result.best_move, lines[0].pv[0]);
+        assert_eq!(result.info.score, lines[0].score);
+    }
+
+    #[test]
+    fn forward_pruning_stays_within_its_depth_and_margin() {
+        let mut board = middlegame();
+        let params = SearchParams::default();
+        with_search(&mut board, Side::White, &params, |search| {
+            //Margins 60, 150 and 75 per ply
+            assert!(search.can_prune_reverse_futility(3, 0, 180));
+            assert!(!search.can_prune_reverse_futility(3, 0, 179));
+            assert!(!search.can_prune_reverse_futility(4, 0, 1000));
+            assert!(!search.can_prune_reverse_futility(1, -MATE_BOUND, 0));
+
+            assert!(search.can_razor(2, 0, -301));
+            assert!(!search.can_razor(2, 0, -300));
+            assert!(!search.can_razor(3, 0, -1000));
+
+            assert!(search.is_futile(2, 0, -150));
+            assert!(!search.is_futile(2, 0, -149));
+            assert!(!search.is_futile(3, 0, -1000));
+
+            //Three moves plus depth squared are searched first
+            assert!(search.is_late_move(2, 7));
+            assert!(!search.is_late_move(2, 6));
+            assert!(!search.is_late_move(4, 100));
+        });
+
+        let params = SearchParams {
+            reverse_futility_pruning: false,
+            razoring: false,
+            futility_pruning: false,
+            late_move_pruning: false,
+            ..Default::default()
+        };
+        with_search(&mut board, Side::White, &params, |search| {
+            assert!(!search.can_prune_reverse_futility(1, 0, 1000));
+            assert!(!search.can_razor(1, 0, -1000));
+            assert!(!search.is_futile(1, 0, -1000));
+            assert!(!search.is_late_move(1, 100));
+        });
+    }
+
+    fn trace_events(node: &TraceNode, events: &mut Vec<TraceEvent>) {
+        events.extend(node.events.iter().cloned());
+        node.children.iter().for_each(|child| trace_events(child, events));
+    }
+
+    fn pruning_events(params: SearchParams) -> Vec<TraceEvent> {
+        let params = SearchParams { trace_depth: Some(usize::MAX), ..params };
+        let trace = run_search(&middlegame(), Side::White, &params, 4).unwrap().trace.unwrap();
+        let mut events = vec![];
+        trace.roots.iter().for_each(|root| trace_events(root, &mut events));
+        events.retain(|event| matches!(event,
+            TraceEvent::ReverseFutility | TraceEvent::Razored
+                | TraceEvent::FutilityPruned { .. } | TraceEvent::LateMovePruned { .. }));
+        events
+    }
+
+    #[test]
+    fn switched_off_forward_pruning_leaves_no_trace() {
+        let events = pruning_events(SearchParams::default());
+        assert!(events.iter().any(|event| matches!(event, TraceEvent::ReverseFutility)));
+        assert!(events.iter().any(|event| matches!(event, TraceEvent::LateMovePruned { .. })));
+
+        let params = SearchParams {
+            reverse_futility_pruning: false,
+            razoring: false,
+            futility_pruning: false,
+            late_move_pruning: false,
+            ..Default::default()
+        };
+        assert!(pruning_events(params).is_empty());
+    }
 }
@@ -2,4 +2,21 @@ pub mod board;
 pub mod piece;
 pub mod r#move;
 pub mod minimax;
+pub mod mcts;
+pub mod polyglot;
+pub mod tablebase;
+pub mod see;
+pub mod evaluation;
+pub mod pawns;
+pub mod king_safety;
+pub mod activity;
+pub mod trace;
 pub mod board_view;
+pub mod zobrist;
+pub mod transposition;
+pub mod difficulty;
+pub mod engine;
+pub mod engine_worker;
+pub mod score;
+#[cfg(test)]
+mod test_positions;
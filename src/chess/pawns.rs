@@ -0,0 +1,260 @@
+use crate::{Side, Board, swap_turn};
+use super::evaluation::Tapered;
+use super::piece::ChessPiece;
+
+//Entries in a table made with `PawnTable::default`, about half a megabyte
+const DEFAULT_ENTRIES: usize = 1 << 14;
+
+/// Weights of the pawn-structure terms, counted per pawn. Pawns here only ever move and
+/// capture straight ahead, so they never change files and only a pawn in front on the same
+/// file can stop one.
+#[derive(Clone, Debug)]
+pub struct PawnParams {
+    /// For every pawn with another pawn of its side further up the file.
+    pub doubled: Tapered,
+    /// For a pawn without pawns of its side on the neighbouring files.
+    pub isolated: Tapered,
+    /// For a pawn its neighbours have all left behind while an enemy pawn guards the
+    /// square in front of it.
+    pub backward: Tapered,
+    /// For a pawn with a pawn of its side beside it or diagonally behind it.
+    pub connected: Tapered,
+    /// For a pawn with no pawn in front of it, by rank counted from its own side.
+    pub passed: [Tapered; 8],
+    /// Replaces `passed` while a piece stands on the square in front of the pawn.
+    pub passed_blocked: [Tapered; 8]
+}
+
+impl Default for PawnParams {
+    fn default() -> PawnParams {
+        PawnParams {
+            doubled: Tapered::new(-5, -10),
+            isolated: Tapered::new(-5, -8),
+            backward: Tapered::new(-4, -6),
+            connected: Tapered::new(3, 4),
+            passed: [
+                Tapered::new(0, 0), Tapered::new(0, 3), Tapered::new(2, 5), Tapered::new(4, 10),
+                Tapered::new(8, 18), Tapered::new(15, 30), Tapered::new(25, 45), Tapered::new(25, 45)
+            ],
+            passed_blocked: [
+                Tapered::new(0, 0), Tapered::new(0, 1), Tapered::new(1, 2), Tapered::new(2, 5),
+                Tapered::new(4, 9), Tapered::new(7, 15), Tapered::new(12, 22), Tapered::new(12, 22)
+            ]
+        }
+    }
+}
+
+impl PawnParams {
+    /// Every weight zero, tests switch the terms they look at back on.
+    #[cfg(test)]
+    pub fn zero() -> PawnParams {
+        PawnParams {
+            doubled: Tapered::default(),
+            isolated: Tapered::default(),
+            backward: Tapered::default(),
+            connected: Tapered::default(),
+            passed: [Tapered::default(); 8],
+            passed_blocked: [Tapered::default(); 8]
+        }
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    //White minus black, without the passed pawn bonuses
+    structure: Tapered,
+    //Squares of both sides' passed pawns, bit y * 8 + x
+    passed: u64
+}
+
+/// Pawn-structure scores by the pawn-only Zobrist key. Pawns move rarely, so most positions
+/// a search visits find their structure here. Each search thread keeps its own.
+pub struct PawnTable {
+    entries: Vec<PawnEntry>
+}
+
+impl Default for PawnTable {
+    fn default() -> PawnTable {
+        PawnTable::new(DEFAULT_ENTRIES)
+    }
+}
+
+/// Direction along y the side's pawns move in.
+pub fn forward(side: Side) -> i8 {
+    match side {
+        Side::White => -1,
+        Side::Black => 1
+    }
+}
+
+/// Rank counted from the side's own end of the board, its pawns start on 1.
+pub fn relative_rank(side: Side, y: i8) -> usize {
+    match side {
+        Side::White => (7 - y) as usize,
+        Side::Black => y as usize
+    }
+}
+
+//Pawns by square, indexed [y][x] like the board
+type PawnGrid = [[Option<Side>; 8]; 8];
+
+fn pawn_on(grid: &PawnGrid, (x, y): (i8, i8)) -> Option<Side> {
+    if !(0..8).contains(&x) || !(0..8).contains(&y) {return None;}
+    grid[y as usize][x as usize]
+}
+
+//Structure of the pawns and the squares of the passed ones
+fn pawn_structure(pieces: &[(ChessPiece, Side, (i8, i8))], params: &PawnParams) -> (Tapered, u64) {
+    let mut grid: PawnGrid = [[None; 8]; 8];
+    for (_, side, (x, y)) in pieces.iter().filter(|(piece, _, _)| *piece == ChessPiece::Pawn) {
+        grid[*y as usize][*x as usize] = Some(*side);
+    }
+
+    let mut structure = Tapered::default();
+    let mut passed: u64 = 0;
+    for (y, row) in grid.iter().enumerate() {
+        for (x, pawn) in row.iter().enumerate() {
+            let side = match pawn {
+                Some(side) => *side,
+                None => continue
+            };
+            let (x, y) = (x as i8, y as i8);
+            let step = forward(side);
+            let rank = relative_rank(side, y);
+            let mut score = Tapered::default();
+
+            let ahead: Vec<Side> = (1..8)
+                .filter_map(|distance| pawn_on(&grid, (x, y + step * distance)))
+                .collect();
+            if ahead.contains(&side) {score += params.doubled;}
+            if ahead.is_empty() {passed |= 1 << (y * 8 + x);}
+
+            //Ranks of this side's pawns on the neighbouring files
+            let neighbours: Vec<usize> = (0..8)
+                .flat_map(|row| [(x - 1, row), (x + 1, row)])
+                .filter(|square| pawn_on(&grid, *square) == Some(side))
+                .map(|(_, row)| relative_rank(side, row))
+                .collect();
+            if neighbours.is_empty() {
+                score += params.isolated;
+            } else if neighbours.iter().all(|neighbour| *neighbour > rank)
+                && pawn_on(&grid, (x, y + 2 * step)) == Some(swap_turn(side)) {
+                score += params.backward;
+            }
+
+            let connected = [x - 1, x + 1].iter()
+                .any(|file| pawn_on(&grid, (*file, y)) == Some(side) || pawn_on(&grid, (*file, y - step)) == Some(side));
+            if connected {score += params.connected;}
+
+            structure += score * side as i32;
+        }
+    }
+    (structure, passed)
+}
+
+impl PawnTable {
+    pub fn new(entries: usize) -> PawnTable {
+        PawnTable { entries: vec![PawnEntry::default(); entries.max(1)] }
+    }
+
+    /// Pawn structure, white minus black. Passed pawns are scored on every call since
+    /// whether a piece blocks them is not part of the key.
+    pub fn probe(&mut self, board: &Board, pieces: &[(ChessPiece, Side, (i8, i8))], params: &PawnParams) -> Tapered {
+        let key = board.pawn_hash();
+        let index = (key % self.entries.len() as u64) as usize;
+        //An empty slot holds key 0 with a zero score, which is also right for no pawns at all
+        if self.entries[index].key != key {
+            let (structure, passed) = pawn_structure(pieces, params);
+            self.entries[index] = PawnEntry { key, structure, passed };
+        }
+        let entry = self.entries[index];
+
+        let mut score = entry.structure;
+        for square in (0..64).filter(|square| entry.passed & (1 << square) != 0) {
+            let (x, y) = ((square % 8) as i8, (square / 8) as i8);
+            let side = match board.piece_on((x, y)) {
+                Some((_, side)) => side,
+                None => continue
+            };
+            let rank = relative_rank(side, y);
+            let front = (x, y + forward(side));
+            let blocked = (0..8).contains(&front.1) && board.piece_on(front).is_some();
+            let bonus = if blocked {params.passed_blocked[rank]} else {params.passed[rank]};
+            score += bonus * side as i32;
+        }
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pawns(white: &[(i8, i8)], black: &[(i8, i8)]) -> Vec<(ChessPiece, Side, (i8, i8))> {
+        let white = white.iter().map(|square| (ChessPiece::Pawn, Side::White, *square));
+        let black = black.iter().map(|square| (ChessPiece::Pawn, Side::Black, *square));
+        white.chain(black).collect()
+    }
+
+    fn structure(pieces: &[(ChessPiece, Side, (i8, i8))], params: &PawnParams) -> Tapered {
+        pawn_structure(pieces, params).0
+    }
+
+    #[test]
+    fn rear_doubled_pawn_is_penalised() {
+        let params = PawnParams { doubled: Tapered::new(-5, -10), ..PawnParams::zero() };
+        assert_eq!(structure(&pawns(&[(2, 6), (2, 5)], &[(5, 1)]), &params), Tapered::new(-5, -10));
+    }
+
+    #[test]
+    fn pawn_without_neighbours_is_isolated() {
+        let params = PawnParams { isolated: Tapered::new(-5, -8), ..PawnParams::zero() };
+        assert_eq!(structure(&pawns(&[(0, 6)], &[(4, 1), (5, 1)]), &params), Tapered::new(-5, -8));
+    }
+
+    #[test]
+    fn pawn_left_behind_under_a_pawn_is_backward() {
+        let params = PawnParams { backward: Tapered::new(-4, -6), ..PawnParams::zero() };
+        //The black pawn on (4, 4) guards (4, 5), the neighbour on (5, 5) has moved on
+        assert_eq!(structure(&pawns(&[(4, 6), (5, 5)], &[(4, 4)]), &params), Tapered::new(-4, -6));
+        //Without the black pawn nothing holds it back
+        assert_eq!(structure(&pawns(&[(4, 6), (5, 5)], &[]), &params), Tapered::default());
+    }
+
+    #[test]
+    fn side_by_side_and_diagonal_pawns_are_connected() {
+        let params = PawnParams { connected: Tapered::new(3, 4), ..PawnParams::zero() };
+        assert_eq!(structure(&pawns(&[(3, 6), (4, 6)], &[(0, 1)]), &params), Tapered::new(6, 8));
+        //Only the pawn in front has its neighbour diagonally behind it
+        assert_eq!(structure(&pawns(&[(3, 6), (4, 5)], &[(0, 1)]), &params), Tapered::new(3, 4));
+    }
+
+    #[test]
+    fn pawn_with_nothing_in_front_is_passed() {
+        let params = PawnParams { passed: PawnParams::default().passed, ..PawnParams::zero() };
+        let pieces = pawns(&[(4, 3), (0, 6)], &[(0, 1)]);
+        let mut table = PawnTable::new(16);
+        //The pawns on x = 0 stop each other, only the one on (4, 3) is passed
+        assert_eq!(table.probe(&Board::from_pieces(&pieces), &pieces, &params), params.passed[4]);
+    }
+
+    #[test]
+    fn blocked_passer_gets_the_smaller_bonus() {
+        let params = PawnParams::default();
+        let pieces = pawns(&[(4, 3), (0, 6)], &[(0, 1)]);
+        let open = Board::from_pieces(&pieces);
+        let mut blockaded = pieces.clone();
+        blockaded.push((ChessPiece::Knight, Side::Black, (4, 2)));
+        let blockaded = Board::from_pieces(&blockaded);
+        assert_eq!(open.pawn_hash(), blockaded.pawn_hash());
+
+        let expected = structure(&pieces, &params);
+        let mut table = PawnTable::new(16);
+        assert_eq!(table.probe(&open, &pieces, &params), expected + params.passed[4]);
+        //Same pawns, so the structure comes from the table without looking at the pieces,
+        //while the blockade is still seen on the board
+        assert_eq!(table.probe(&blockaded, &[], &params), expected + params.passed_blocked[4]);
+        assert_eq!(table.probe(&open, &[], &params), expected + params.passed[4]);
+    }
+}
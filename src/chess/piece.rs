@@ -10,7 +10,7 @@ pub enum Side {
     Black = -1
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum ChessPiece {
     King = 0,
     Queen = 1,
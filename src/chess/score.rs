@@ -0,0 +1,184 @@
+use std::fmt;
+
+use super::piece::ChessPiece;
+
+/// Mate on the board, faster mates score closer to it.
+pub const MATE: i32 = 10_000_000;
+/// Anything beyond this is a forced mate.
+pub const MATE_BOUND: i32 = MATE - MAX_PLY;
+pub const MAX_PLY: i32 = 1000;
+/// Tablebase wins, below every mate and above every evaluation.
+pub const TB_WIN: i32 = MATE_BOUND - MAX_PLY;
+/// Anything beyond this is a tablebase win found some plies from the root, or a mate.
+pub const TB_WIN_BOUND: i32 = TB_WIN - MAX_PLY;
+//Reported to front-ends for a tablebase win at the root, mates stay above it
+const TB_WIN_CENTIPAWNS: i32 = 20_000;
+
+/// Search score from the point of view of the side to move: material units or a forced mate.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Score(pub i32);
+
+impl Score {
+    /// The side to move is mated, `ply` half-moves from the root.
+    pub fn mated_in(ply: usize) -> Score {
+        Score(-MATE + ply as i32)
+    }
+
+    pub fn is_mate(self) -> bool {
+        self.0.abs() >= MATE_BOUND
+    }
+
+    /// The side to move has been mated on the board, there is no move left to play.
+    pub fn is_mated(self) -> bool {
+        self == Score::mated_in(0)
+    }
+
+    /// Won or lost according to the tablebases rather than a mate the search found.
+    pub fn is_tablebase_result(self) -> bool {
+        !self.is_mate() && self.0.abs() >= TB_WIN_BOUND
+    }
+
+    /// Full moves until mate, negative when the side to move is the one getting mated.
+    /// A side already mated gets 0, which carries no sign, so check `is_mated` for it.
+    pub fn mate_distance(self) -> Option<i32> {
+        if !self.is_mate() {return None;}
+        let moves = (MATE - self.0.abs() + 1) / 2;
+        Some(if self.0 > 0 {moves} else {-moves})
+    }
+
+    pub fn centipawns(self) -> i32 {
+        self.0 * 100 / ChessPiece::get_material_price(&ChessPiece::Pawn)
+    }
+
+    /// "mate 5" / "mate -3" / "cp 120", as protocol front-ends expect it, "mate 0" once
+    /// mated. Tablebase wins have no notation of their own and are sent as a large
+    /// centipawn score that shrinks with the plies to the tablebase position.
+    pub fn to_protocol_string(self) -> String {
+        if self.is_tablebase_result() {
+            let centipawns = TB_WIN_CENTIPAWNS - (TB_WIN - self.0.abs());
+            return format!("cp {}", if self.0 > 0 {centipawns} else {-centipawns});
+        }
+        match self.mate_distance() {
+            Some(moves) => format!("mate {}", moves),
+            None => format!("cp {}", self.centipawns())
+        }
+    }
+}
+
+//Mates and tablebase wins are stored relative to the node rather than the root, so a
+//table entry stays right when the position comes up again at a different ply
+pub fn to_table(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN_BOUND {
+        score + ply as i32
+    } else if score <= -TB_WIN_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+pub fn from_table(score: i32, ply: usize) -> i32 {
+    if score >= TB_WIN_BOUND {
+        score - ply as i32
+    } else if score <= -TB_WIN_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+/// "#5" / "-#3" for mates and "-#0" once mated, "TB win" / "TB loss" for tablebase
+/// results, pawns with two decimals otherwise.
+impl fmt::Display for Score {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_tablebase_result() {
+            return write!(f, "{}", if self.0 > 0 {"TB win"} else {"TB loss"});
+        }
+        if self.is_mated() {return write!(f, "-#0");}
+        match self.mate_distance() {
+            Some(moves) if moves < 0 => write!(f, "-#{}", -moves),
+            Some(moves) => write!(f, "#{}", moves),
+            None => write!(f, "{:+.2}", self.centipawns() as f32 / 100.0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mate_distance_counts_full_moves() {
+        //Mating move on the 9th ply
+        assert_eq!(Score(MATE - 9).mate_distance(), Some(5));
+        assert_eq!(Score(MATE - 1).mate_distance(), Some(1));
+        assert_eq!(Score::mated_in(6).mate_distance(), Some(-3));
+        //Already mated, only is_mated tells it from a mate being delivered
+        assert_eq!(Score::mated_in(0).mate_distance(), Some(0));
+        assert!(Score::mated_in(0).is_mated());
+        assert!(!Score::mated_in(2).is_mated());
+        assert!(!Score(MATE).is_mated());
+        assert_eq!(Score(TB_WIN).mate_distance(), None);
+        assert_eq!(Score(-120).mate_distance(), None);
+    }
+
+    #[test]
+    fn scores_display_as_mates_or_pawns() {
+        assert_eq!(Score(MATE - 9).to_string(), "#5");
+        assert_eq!(Score::mated_in(6).to_string(), "-#3");
+        assert_eq!(Score::mated_in(0).to_string(), "-#0");
+        assert_eq!(Score(60).to_string(), "+1.20");
+        assert_eq!(Score(-25).to_string(), "-0.50");
+        assert_eq!(Score(0).to_string(), "+0.00");
+
+        assert_eq!(Score(MATE - 9).to_protocol_string(), "mate 5");
+        assert_eq!(Score::mated_in(6).to_protocol_string(), "mate -3");
+        assert_eq!(Score::mated_in(0).to_protocol_string(), "mate 0");
+        assert_eq!(Score(60).to_protocol_string(), "cp 120");
+    }
+
+    #[test]
+    fn mates_are_stored_relative_to_the_node() {
+        //Mate found 4 plies below a node 3 plies from the root
+        let stored = to_table(MATE - 7, 3);
+        assert_eq!(stored, MATE - 4);
+        assert_eq!(from_table(stored, 3), MATE - 7);
+        //Reached again 5 plies from the root the mate is 2 plies further away
+        assert_eq!(from_table(stored, 5), MATE - 9);
+
+        let stored = to_table(-MATE + 7, 3);
+        assert_eq!(stored, -MATE + 4);
+        assert_eq!(from_table(stored, 5), -MATE + 9);
+    }
+
+    #[test]
+    fn evaluations_are_stored_unchanged() {
+        for score in [0, 75, -340, TB_WIN_BOUND - 1, -(TB_WIN_BOUND - 1)] {
+            assert_eq!(to_table(score, 7), score);
+            assert_eq!(from_table(score, 7), score);
+        }
+    }
+
+    #[test]
+    fn tablebase_results_are_not_centipawns() {
+        assert!(Score(TB_WIN).is_tablebase_result());
+        assert!(Score(-TB_WIN + 12).is_tablebase_result());
+        assert!(!Score(MATE - 3).is_tablebase_result());
+        assert!(!Score(500).is_tablebase_result());
+
+        assert_eq!(Score(TB_WIN).to_string(), "TB win");
+        assert_eq!(Score(-TB_WIN + 12).to_string(), "TB loss");
+        assert_eq!(Score(TB_WIN).to_protocol_string(), "cp 20000");
+        assert_eq!(Score(-TB_WIN + 12).to_protocol_string(), "cp -19988");
+    }
+
+    #[test]
+    fn tablebase_scores_are_stored_relative_to_the_node() {
+        //Won 3 plies below a node that is 5 plies from the root
+        let stored = to_table(TB_WIN - 8, 5);
+        assert_eq!(stored, TB_WIN - 3);
+        //The same node reached 2 plies from the root
+        assert_eq!(from_table(stored, 2), TB_WIN - 5);
+        assert_eq!(from_table(to_table(-TB_WIN + 8, 5), 2), -TB_WIN + 5);
+    }
+}
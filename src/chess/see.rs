@@ -0,0 +1,129 @@
+use crate::{Side, Board, swap_turn};
+use super::board::Move;
+use super::piece::ChessPiece;
+
+fn price(piece: ChessPiece) -> i32 {
+    ChessPiece::get_material_price(&piece)
+}
+
+//Cheapest piece of `side` still able to capture on the square
+fn least_valuable_attacker(board: &Board, square: (i8, i8), side: Side, removed: &[(i8, i8)]) -> Option<(ChessPiece, (i8, i8))> {
+    board.attackers(square, side, removed).into_iter()
+        .min_by_key(|(piece, _)| price(*piece))
+}
+
+/// Static exchange evaluation: material won or lost by `l_move` once both sides have
+/// recaptured on its target square for as long as it pays off, each time with their
+/// cheapest attacker. Sliders lined up behind a piece join in after it has captured.
+/// Positive wins material, 0 is an even trade and quiet moves score what the moving
+/// piece can lose.
+pub fn static_exchange(board: &Board, l_move: &Move) -> i32 {
+    let (mut on_square, mut side) = match board.piece_on(l_move.from) {
+        Some(moving) => moving,
+        None => return 0
+    };
+    let mut removed = vec![l_move.from];
+    //gains[n] is the material balance for the side making capture n if the exchange stops there
+    let mut gains = vec![board.captured_piece(l_move).map_or(0, price)];
+
+    loop {
+        side = swap_turn(side);
+        let (attacker, square) = match least_valuable_attacker(board, l_move.to, side, &removed) {
+            Some(attacker) => attacker,
+            None => break
+        };
+        //The king cannot capture onto a defended square. A recapture by it is taken back,
+        //the move itself costs the king
+        if on_square == ChessPiece::King {
+            if gains.len() > 1 {
+                gains.pop();
+            } else {
+                gains.push(price(on_square) - gains[0]);
+            }
+            break;
+        }
+
+        let previous = gains[gains.len() - 1];
+        gains.push(price(on_square) - previous);
+        on_square = attacker;
+        removed.push(square);
+    }
+
+    //Either side stops recapturing once that would lose more than it wins
+    while gains.len() > 1 {
+        let last = gains.pop().unwrap_or(0);
+        let previous = gains.len() - 1;
+        gains[previous] = -(-gains[previous]).max(last);
+    }
+    gains[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rook_takes_knight() -> Move {
+        Move { from: (0, 6), to: (0, 3) }
+    }
+
+    //Boards hold only the pieces taking part, exchanges do not need the kings
+    #[test]
+    fn undefended_capture_wins_the_piece() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::Rook, Side::White, (0, 6)),
+            (ChessPiece::Knight, Side::Black, (0, 3))
+        ]);
+        assert_eq!(static_exchange(&board, &rook_takes_knight()), 250);
+    }
+
+    #[test]
+    fn defended_capture_loses_the_difference() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::Rook, Side::White, (0, 6)),
+            (ChessPiece::Knight, Side::Black, (0, 3)),
+            (ChessPiece::Rook, Side::Black, (0, 0))
+        ]);
+        assert_eq!(static_exchange(&board, &rook_takes_knight()), 250 - 400);
+    }
+
+    #[test]
+    fn rook_behind_joins_the_exchange() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::Rook, Side::White, (0, 7)),
+            (ChessPiece::Rook, Side::White, (0, 6)),
+            (ChessPiece::Knight, Side::Black, (0, 3)),
+            (ChessPiece::Rook, Side::Black, (0, 0))
+        ]);
+        //Black does not recapture once the second rook backs up the first
+        assert_eq!(static_exchange(&board, &rook_takes_knight()), 250);
+    }
+
+    #[test]
+    fn king_only_recaptures_undefended_pieces() {
+        let defended_by_king = [
+            (ChessPiece::Rook, Side::White, (0, 6)),
+            (ChessPiece::Knight, Side::Black, (0, 3)),
+            (ChessPiece::King, Side::Black, (1, 2))
+        ];
+        assert_eq!(static_exchange(&Board::from_pieces(&defended_by_king), &rook_takes_knight()), 250 - 400);
+
+        let mut backed_up = defended_by_king.to_vec();
+        backed_up.push((ChessPiece::Knight, Side::White, (2, 4)));
+        assert_eq!(static_exchange(&Board::from_pieces(&backed_up), &rook_takes_knight()), 250);
+    }
+
+    #[test]
+    fn king_taking_a_defended_piece_loses_the_king() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (4, 5)),
+            (ChessPiece::Pawn, Side::Black, (4, 4)),
+            (ChessPiece::Knight, Side::Black, (5, 2))
+        ]);
+        let king_takes_pawn = Move { from: (4, 5), to: (4, 4) };
+        assert_eq!(static_exchange(&board, &king_takes_pawn), 50 - 1000);
+
+        let mut undefended = board.clone();
+        undefended.set_piece((5, 2), None);
+        assert_eq!(static_exchange(&undefended, &king_takes_pawn), 50);
+    }
+}
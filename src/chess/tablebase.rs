@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::{Side, Board, swap_turn};
+use super::board::Move;
+use super::piece::ChessPiece;
+
+mod syzygy;
+pub use syzygy::SyzygyTables;
+
+/// Largest pawn endings the generator builds, kings included.
+pub const MAX_PIECES: usize = 3;
+
+const FILE_MAGIC: &[u8; 4] = b"CMTB";
+const FILE_EXTENSION: &str = "tb";
+//Pieces of a side are listed in this order, as in Syzygy file names
+const PIECE_ORDER: [ChessPiece; 6] = [
+    ChessPiece::King,
+    ChessPiece::Queen,
+    ChessPiece::Rook,
+    ChessPiece::Bishop,
+    ChessPiece::Knight,
+    ChessPiece::Pawn
+];
+
+//Stored WDL codes, from the point of view of the side to move
+const ILLEGAL: u8 = 0;
+const LOSS: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 3;
+const UNKNOWN: u8 = 4;
+
+//Successor codes used while generating. Captures leave the table, their result is
+//looked up right away and stored as one of the codes at the top of the range
+const ZEROING: u32 = 1 << 31;
+const LEFT_TABLE: u32 = ZEROING | (1 << 30);
+const UNRESOLVED_DTZ: u16 = u16::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    Draw,
+    Win
+}
+
+/// Tablebase result for the side to move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Probe {
+    pub wdl: Wdl,
+    /// Plies to the next capture, pawn move or mate with best play, 0 when already mated.
+    pub dtz: u16
+}
+
+struct Table {
+    piece_count: usize,
+    wdl: Vec<u8>,
+    dtz: Vec<u16>
+}
+
+/// Win/draw/loss and distance-to-zeroing tables: Syzygy files for endgames without pawns,
+/// generated tables for pawn endings of up to `MAX_PIECES`.
+///
+/// Pawns here only ever step one square straight ahead and capture the same way, so Syzygy
+/// tables with pawns don't apply. Those endings are generated by retrograde analysis with
+/// this board's own move generator and kept in a cache directory, one file per endgame
+/// named the Syzygy way (`KPvK.tb`, white's pieces first).
+#[derive(Default)]
+pub struct Tablebases {
+    syzygy: SyzygyTables,
+    //Generated pawn endings
+    tables: HashMap<String, Table>
+}
+
+fn piece_letter(piece: ChessPiece) -> char {
+    match piece {
+        ChessPiece::King => 'K',
+        ChessPiece::Queen => 'Q',
+        ChessPiece::Rook => 'R',
+        ChessPiece::Bishop => 'B',
+        ChessPiece::Knight => 'N',
+        ChessPiece::Pawn => 'P'
+    }
+}
+
+fn piece_rank(piece: ChessPiece) -> usize {
+    PIECE_ORDER.iter().position(|ordered| *ordered == piece).unwrap_or(PIECE_ORDER.len())
+}
+
+//White's pieces first, each side in PIECE_ORDER
+fn sort_pieces(pieces: &mut [(ChessPiece, Side, (i8, i8))]) {
+    pieces.sort_by_key(|(piece, side, _)| (*side == Side::Black, piece_rank(*piece)));
+}
+
+fn material_name(pieces: &[(ChessPiece, Side, (i8, i8))]) -> String {
+    let side_letters = |side: Side| pieces.iter()
+        .filter(|(_, piece_side, _)| *piece_side == side)
+        .map(|(piece, _, _)| piece_letter(*piece))
+        .collect::<String>();
+    format!("{}v{}", side_letters(Side::White), side_letters(Side::Black))
+}
+
+//Side to move in the top bit, then one 6-bit square per piece in table order
+fn position_index(whose_turn: Side, squares: impl Iterator<Item = (i8, i8)>) -> usize {
+    let turn_bit = match whose_turn {
+        Side::White => 0,
+        Side::Black => 1
+    };
+    squares.fold(turn_bit, |index, (x, y)| index * 64 + (y * 8 + x) as usize)
+}
+
+fn decode_index(index: usize, piece_count: usize) -> (Side, Vec<(i8, i8)>) {
+    let squares: Vec<(i8, i8)> = (0..piece_count)
+        .map(|slot| {
+            let square = (index >> (6 * (piece_count - 1 - slot))) & 63;
+            ((square % 8) as i8, (square / 8) as i8)
+        })
+        .collect();
+    let whose_turn = if index >> (6 * piece_count) == 0 {Side::White} else {Side::Black};
+    (whose_turn, squares)
+}
+
+fn wdl_from_code(code: u8) -> Option<Wdl> {
+    match code {
+        LOSS => Some(Wdl::Loss),
+        DRAW => Some(Wdl::Draw),
+        WIN => Some(Wdl::Win),
+        _ => None
+    }
+}
+
+//Every endgame of `piece_count` pieces with both kings, white's extra pieces first
+fn materials(piece_count: usize) -> Vec<Vec<(ChessPiece, Side)>> {
+    let extra_pieces = &PIECE_ORDER[1..];
+    let mut materials: Vec<Vec<(ChessPiece, Side)>> = vec![vec![]];
+    for _ in 2..piece_count {
+        materials = materials.into_iter()
+            .flat_map(|material| {
+                extra_pieces.iter().flat_map(move |piece| {
+                    let material = material.clone();
+                    [Side::White, Side::Black].into_iter().map(move |side| {
+                        let mut extended = material.clone();
+                        extended.push((*piece, side));
+                        extended
+                    })
+                })
+            })
+            .collect();
+    }
+    let mut unique: Vec<Vec<(ChessPiece, Side)>> = vec![];
+    for mut material in materials {
+        material.push((ChessPiece::King, Side::White));
+        material.push((ChessPiece::King, Side::Black));
+        material.sort_by_key(|(piece, side)| (*side == Side::Black, piece_rank(*piece)));
+        if !unique.contains(&material) {unique.push(material);}
+    }
+    unique
+}
+
+impl Table {
+    fn read(bytes: &[u8]) -> io::Result<Table> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_owned());
+        if bytes.len() < 5 || &bytes[..4] != FILE_MAGIC {return Err(invalid("not a tablebase file"));}
+        let piece_count = bytes[4] as usize;
+        if !(2..=MAX_PIECES).contains(&piece_count) {return Err(invalid("unsupported piece count"));}
+
+        let size = 2 << (6 * piece_count);
+        let data = &bytes[5..];
+        if data.len() != size * 3 {return Err(invalid("truncated tablebase file"));}
+        let dtz = data[size..].chunks_exact(2)
+            .map(|dtz| u16::from_le_bytes([dtz[0], dtz[1]]))
+            .collect();
+        Ok(Table { piece_count, wdl: data[..size].to_vec(), dtz })
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut bytes = Vec::with_capacity(5 + self.wdl.len() * 3);
+        bytes.extend_from_slice(FILE_MAGIC);
+        bytes.push(self.piece_count as u8);
+        bytes.extend_from_slice(&self.wdl);
+        for dtz in &self.dtz {
+            bytes.extend_from_slice(&dtz.to_le_bytes());
+        }
+        fs::write(path, bytes)
+    }
+}
+
+impl Tablebases {
+    /// Finds the Syzygy tables in `directory`, nothing is ever written there.
+    pub fn open(directory: &Path) -> io::Result<Tablebases> {
+        Ok(Tablebases { syzygy: SyzygyTables::open(directory)?, tables: HashMap::new() })
+    }
+
+    /// Loads the generated pawn endings up to `max_pieces` from `cache`, generating and
+    /// writing there the ones it is missing first. Returns how many were generated.
+    /// Captures in a pawn ending of up to `MAX_PIECES` only ever lead to bare kings.
+    pub fn load_pawn_endings(&mut self, cache: &Path, max_pieces: usize) -> io::Result<usize> {
+        fs::create_dir_all(cache)?;
+        for dir_entry in fs::read_dir(cache)? {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(FILE_EXTENSION) {continue;}
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) if name.contains('P') => name.to_owned(),
+                _ => continue
+            };
+            let table = Table::read(&fs::read(&path)?)
+                .map_err(|error| io::Error::new(error.kind(), format!("{}: {}", path.display(), error)))?;
+            self.tables.insert(name, table);
+        }
+
+        let mut generated = 0;
+        for piece_count in 3..=max_pieces.min(MAX_PIECES) {
+            for material in materials(piece_count) {
+                if !material.iter().any(|(piece, _)| *piece == ChessPiece::Pawn) {continue;}
+                let pieces: Vec<(ChessPiece, Side, (i8, i8))> = material.iter()
+                    .map(|(piece, side)| (*piece, *side, (0, 0)))
+                    .collect();
+                let name = material_name(&pieces);
+                if self.tables.contains_key(&name) {continue;}
+
+                let table = self.generate_table(&material);
+                table.write(&cache.join(format!("{}.{}", name, FILE_EXTENSION)))?;
+                self.tables.insert(name, table);
+                generated += 1;
+            }
+        }
+        Ok(generated)
+    }
+
+    pub fn len(&self) -> usize {
+        self.syzygy.len() + self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn syzygy(&self) -> &SyzygyTables {
+        &self.syzygy
+    }
+
+    /// Most pieces any table covers.
+    pub fn max_pieces(&self) -> usize {
+        let generated = self.tables.values().map(|table| table.piece_count).max().unwrap_or(0);
+        generated.max(self.syzygy.max_pieces())
+    }
+
+    //Code for a position given as sorted pieces, bare kings are always a draw
+    fn probe_code(&self, pieces: &[(ChessPiece, Side, (i8, i8))], whose_turn: Side) -> Option<(u8, u16)> {
+        if pieces.len() == 2 {return Some((DRAW, 0));}
+        let table = self.tables.get(&material_name(pieces))?;
+        let index = position_index(whose_turn, pieces.iter().map(|(_, _, square)| *square));
+        Some((table.wdl[index], table.dtz[index]))
+    }
+
+    /// Result for the side to move, `None` when no table covers the position.
+    pub fn probe(&self, board: &Board, whose_turn: Side) -> Option<Probe> {
+        if let Some(probe) = self.syzygy.probe(board, whose_turn) {return Some(probe);}
+        let mut pieces = board.pieces();
+        if pieces.len() > self.max_pieces().max(2) {return None;}
+        sort_pieces(&mut pieces);
+        let (code, dtz) = self.probe_code(&pieces, whose_turn)?;
+        Some(Probe { wdl: wdl_from_code(code)?, dtz })
+    }
+
+    /// Win, draw or loss alone, which Syzygy tables give without the search `probe`
+    /// needs for the distance.
+    pub fn probe_wdl(&self, board: &Board, whose_turn: Side) -> Option<Wdl> {
+        if let Some(wdl) = self.syzygy.probe_wdl(board, whose_turn) {return Some(wdl);}
+        self.probe(board, whose_turn).map(|probe| probe.wdl)
+    }
+
+    /// The DTZ-optimal move: a win by the fastest way to the next capture, pawn move or mate,
+    /// otherwise a draw, otherwise the loss that holds out longest. The probe is for the
+    /// side to move after playing it. Moves into positions no table covers are left out,
+    /// `None` when that leaves nothing.
+    pub fn best_root_move(&self, board: &Board, whose_turn: Side) -> Option<(Move, Probe)> {
+        let mut board = board.clone();
+        let mut best: Option<(Move, Probe, i32)> = None;
+        for l_move in board.get_legal_moves_for_side(whose_turn) {
+            let zeroing = board.piece_on(l_move.to).is_some()
+                || board.piece_on(l_move.from).is_some_and(|(piece, _)| piece == ChessPiece::Pawn);
+            board.make_move(&l_move);
+            let reply = self.probe(&board, swap_turn(whose_turn));
+            board.unmake_move(&l_move);
+
+            let reply = match reply {
+                Some(reply) => reply,
+                None => continue
+            };
+            let dtz = if zeroing {0} else {reply.dtz as i32};
+            let rank = match reply.wdl {
+                Wdl::Loss => 2_000_000 - dtz,
+                Wdl::Draw => 1_000_000,
+                Wdl::Win => dtz
+            };
+            if best.as_ref().is_none_or(|(_, _, best_rank)| rank > *best_rank) {
+                let probe = Probe {
+                    wdl: match reply.wdl {
+                        Wdl::Loss => Wdl::Win,
+                        Wdl::Draw => Wdl::Draw,
+                        Wdl::Win => Wdl::Loss
+                    },
+                    dtz: dtz as u16 + 1
+                };
+                best = Some((l_move, probe, rank));
+            }
+        }
+        best.map(|(l_move, probe, _)| (l_move, probe))
+    }
+
+    //Retrograde analysis over every placement of `material`, with either side to move
+    fn generate_table(&self, material: &[(ChessPiece, Side)]) -> Table {
+        let piece_count = material.len();
+        let size = 2 << (6 * piece_count);
+        let mut board = Board::empty();
+        let mut wdl = vec![ILLEGAL; size];
+        let mut offsets: Vec<u32> = Vec::with_capacity(size + 1);
+        let mut successors: Vec<u32> = vec![];
+
+        for (index, code) in wdl.iter_mut().enumerate() {
+            offsets.push(successors.len() as u32);
+            let (whose_turn, squares) = decode_index(index, piece_count);
+            //Two pieces on one square
+            if (1..piece_count).any(|slot| squares[..slot].contains(&squares[slot])) {continue;}
+
+            for ((piece, side), square) in material.iter().zip(&squares) {
+                board.set_piece(*square, Some((*piece, *side)));
+            }
+            if !board.is_in_check(swap_turn(whose_turn)) {
+                let moves = board.get_legal_moves_for_side(whose_turn);
+                if moves.is_empty() {
+                    *code = if board.is_in_check(whose_turn) {LOSS} else {DRAW};
+                } else {
+                    *code = UNKNOWN;
+                    for l_move in moves {
+                        successors.push(self.successor_code(&board, material, &squares, whose_turn, &l_move));
+                    }
+                }
+            }
+            for square in &squares {
+                board.set_piece(*square, None);
+            }
+        }
+        offsets.push(successors.len() as u32);
+        let moves_of = |index: usize| &successors[offsets[index] as usize..offsets[index + 1] as usize];
+        let successor_wdl = |wdl: &[u8], successor: u32| if successor & LEFT_TABLE == LEFT_TABLE {
+            (successor & 0xFF) as u8
+        } else {
+            wdl[(successor & !ZEROING) as usize]
+        };
+
+        //Wins and losses spread back from the mates until nothing changes, the rest is drawn
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 0..size {
+                if wdl[index] != UNKNOWN {continue;}
+                let mut all_lost = true;
+                let mut found_win = false;
+                for successor in moves_of(index) {
+                    match successor_wdl(&wdl, *successor) {
+                        LOSS => {found_win = true; break;},
+                        WIN => {},
+                        _ => all_lost = false
+                    }
+                }
+                if found_win || all_lost {
+                    wdl[index] = if found_win {WIN} else {LOSS};
+                    changed = true;
+                }
+            }
+        }
+        for code in wdl.iter_mut().filter(|code| **code == UNKNOWN) {
+            *code = DRAW;
+        }
+
+        //Distances in rounds, a position only counts successors settled in earlier rounds
+        let mut dtz = vec![UNRESOLVED_DTZ; size];
+        for index in 0..size {
+            if wdl[index] == LOSS && moves_of(index).is_empty() {dtz[index] = 0;}
+        }
+        let mut round: u16 = 1;
+        loop {
+            let settled = |dtz: &[u16], successor: u32| {
+                successor & ZEROING != 0 || dtz[(successor & !ZEROING) as usize] < round
+            };
+            let mut resolved: Vec<usize> = vec![];
+            for index in 0..size {
+                if dtz[index] != UNRESOLVED_DTZ {continue;}
+                let done = match wdl[index] {
+                    WIN => moves_of(index).iter()
+                        .any(|successor| successor_wdl(&wdl, *successor) == LOSS && settled(&dtz, *successor)),
+                    LOSS => moves_of(index).iter()
+                        .all(|successor| settled(&dtz, *successor)),
+                    _ => false
+                };
+                if done {resolved.push(index);}
+            }
+            if resolved.is_empty() {break;}
+            for index in resolved {
+                dtz[index] = round;
+            }
+            round += 1;
+        }
+        for (distance, code) in dtz.iter_mut().zip(&wdl) {
+            if *code != WIN && *code != LOSS {*distance = 0;}
+        }
+
+        Table { piece_count, wdl, dtz }
+    }
+
+    //Index of the position after the move, or for captures the result looked up in the smaller table
+    fn successor_code(&self, board: &Board, material: &[(ChessPiece, Side)], squares: &[(i8, i8)], whose_turn: Side, l_move: &Move) -> u32 {
+        let moved = squares.iter().position(|square| *square == l_move.from).expect("moves start on a piece");
+        let captured = squares.iter().position(|square| *square == l_move.to);
+        let mut after: Vec<(ChessPiece, Side, (i8, i8))> = material.iter()
+            .zip(squares)
+            .map(|((piece, side), square)| (*piece, *side, *square))
+            .collect();
+        after[moved].2 = l_move.to;
+
+        let opponent = swap_turn(whose_turn);
+        match captured {
+            Some(captured) => {
+                after.remove(captured);
+                let code = self.probe_code(&after, opponent).map_or(DRAW, |(code, _)| code);
+                LEFT_TABLE | code as u32
+            },
+            None => {
+                let index = position_index(opponent, after.iter().map(|(_, _, square)| *square)) as u32;
+                let pawn_move = board.piece_on(l_move.from).is_some_and(|(piece, _)| piece == ChessPiece::Pawn);
+                if pawn_move {index | ZEROING} else {index}
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Tablebases {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut names: Vec<&String> = self.tables.keys().collect();
+        names.sort();
+        f.debug_struct("Tablebases").field("syzygy", &self.syzygy.names()).field("tables", &names).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use super::super::minimax::{search, SearchLimits, SearchParams};
+    use super::super::score::Score;
+    use super::super::transposition::TranspositionTable;
+
+    //Only the tables the tests need, the whole set takes long in a debug build. The Syzygy
+    //tests check their reader against these too
+    pub fn tablebases() -> &'static Arc<Tablebases> {
+        static TABLEBASES: std::sync::OnceLock<Arc<Tablebases>> = std::sync::OnceLock::new();
+        TABLEBASES.get_or_init(|| {
+            let mut tablebases = Tablebases::default();
+            for extra_piece in [ChessPiece::Queen, ChessPiece::Knight] {
+                let material = [(ChessPiece::King, Side::White), (extra_piece, Side::White), (ChessPiece::King, Side::Black)];
+                let table = tablebases.generate_table(&material);
+                let name = format!("K{}vK", piece_letter(extra_piece));
+                tablebases.tables.insert(name, table);
+            }
+            Arc::new(tablebases)
+        })
+    }
+
+    //White's king and one more white piece against black's bare king
+    pub fn king_and_piece_board(piece: ChessPiece, king: (i8, i8), square: (i8, i8), other_king: (i8, i8)) -> Board {
+        Board::from_pieces(&[
+            (ChessPiece::King, Side::White, king),
+            (piece, Side::White, square),
+            (ChessPiece::King, Side::Black, other_king)
+        ])
+    }
+
+    pub fn king_and_queen_board(king: (i8, i8), queen: (i8, i8), other_king: (i8, i8)) -> Board {
+        king_and_piece_board(ChessPiece::Queen, king, queen, other_king)
+    }
+
+    #[test]
+    fn tables_survive_a_round_trip() {
+        let path = std::env::temp_dir().join(format!("chess_minimax_KQvK_{}.tb", std::process::id()));
+        let table = &tablebases().tables["KQvK"];
+        table.write(&path).expect("table is written");
+        let read = Table::read(&fs::read(&path).expect("table is read back"));
+        let _ = fs::remove_file(&path);
+
+        let read = read.expect("file is valid");
+        assert_eq!(read.piece_count, 3);
+        assert!(read.wdl == table.wdl && read.dtz == table.dtz);
+    }
+
+    #[test]
+    fn bare_kings_are_drawn() {
+        let board = Board::from_pieces(&[
+            (ChessPiece::King, Side::White, (4, 4)),
+            (ChessPiece::King, Side::Black, (0, 0))
+        ]);
+        assert_eq!(tablebases().probe(&board, Side::White), Some(Probe { wdl: Wdl::Draw, dtz: 0 }));
+    }
+
+    #[test]
+    fn king_and_queen_win() {
+        let board = king_and_queen_board((4, 4), (7, 7), (0, 0));
+        assert_eq!(tablebases().probe(&board, Side::White).map(|probe| probe.wdl), Some(Wdl::Win));
+        assert_eq!(tablebases().probe(&board, Side::Black).map(|probe| probe.wdl), Some(Wdl::Loss));
+    }
+
+    #[test]
+    fn minor_pieces_cannot_win() {
+        let board = king_and_piece_board(ChessPiece::Knight, (4, 4), (2, 5), (0, 0));
+        assert_eq!(tablebases().probe(&board, Side::White).map(|probe| probe.wdl), Some(Wdl::Draw));
+    }
+
+    #[test]
+    fn stalemate_is_drawn() {
+        //Black king in the corner, the queen covers every square around it without giving check
+        let mut board = king_and_queen_board((7, 7), (1, 2), (0, 0));
+        assert!(board.get_legal_moves_for_side(Side::Black).is_empty());
+        assert_eq!(tablebases().probe(&board, Side::Black), Some(Probe { wdl: Wdl::Draw, dtz: 0 }));
+    }
+
+    #[test]
+    fn root_moves_no_table_covers_are_left_out() {
+        //Only taking the rook leads into a loaded table
+        let mut board = king_and_queen_board((4, 4), (7, 7), (0, 0));
+        board.set_piece((7, 0), Some((ChessPiece::Rook, Side::Black)));
+        let (l_move, probe) = tablebases().best_root_move(&board, Side::White).expect("the capture is covered");
+        assert_eq!(l_move, Move { from: (7, 7), to: (7, 0) });
+        assert_eq!(probe, Probe { wdl: Wdl::Win, dtz: 1 });
+    }
+
+    #[test]
+    fn drawn_root_is_scored_with_contempt() {
+        let board = king_and_piece_board(ChessPiece::Knight, (4, 4), (2, 5), (0, 0));
+        let params = SearchParams { contempt: 20, tablebases: Some(tablebases().clone()), ..Default::default() };
+        let table = TranspositionTable::new(1);
+        let result = search(Side::White, &board, &params, &table, &SearchLimits::default(), &mut |_| {}).expect("white has moves");
+        assert_eq!(result.info.score, Score(-20));
+    }
+}
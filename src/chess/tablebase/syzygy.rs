@@ -0,0 +1,1368 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::{Side, Board, swap_turn};
+use super::super::piece::ChessPiece;
+use super::{material_name, sort_pieces, Probe, Wdl};
+
+/// Largest Syzygy endgames picked up, kings included. Files are read whole the first time
+/// they are probed, the six and seven piece ones are too large for that.
+pub const SYZYGY_MAX_PIECES: usize = 5;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+const WDL_EXTENSION: &str = "rtbw";
+const DTZ_EXTENSION: &str = "rtbz";
+
+//Flags in the file header
+const HAS_PAWNS: u8 = 2;
+//Flags of each compressed table
+const STORED_SIDE: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+//Right half of a symbol that stands for a single value rather than a pair of symbols
+const LEAF: usize = 0xFFF;
+//Placements of the leading group: three unique pieces, or the two kings when there are
+//fewer unique pieces than that
+const UNIQUE_PLACEMENTS: u64 = 31332;
+const KING_PLACEMENTS: u64 = 462;
+//Where each result's DTZ values start in a mapped table: win, loss, cursed win, blessed loss
+const DTZ_MAP_OF_WDL: [usize; 5] = [1, 3, 0, 2, 0];
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+}
+
+//Syzygy squares count from a1 along the ranks, white's first rank is this board's row 7
+fn file_of(square: usize) -> usize {
+    square & 7
+}
+
+fn rank_of(square: usize) -> usize {
+    square >> 3
+}
+
+//Negative below the a1-h8 diagonal, positive above it
+fn off_diagonal(square: usize) -> i32 {
+    rank_of(square) as i32 - file_of(square) as i32
+}
+
+fn flip_diagonal(square: usize) -> usize {
+    ((square >> 3) | (square << 3)) & 63
+}
+
+//Ways to pick `k` of `n` squares
+fn binomial(k: usize, n: usize) -> u64 {
+    if k > n {return 0;}
+    (0..k as u64).fold(1, |ways, i| ways * (n as u64 - i) / (i + 1))
+}
+
+//Pieces are stored as numbers, white's from 1 to 6 and black's 8 higher
+fn piece_code(piece: ChessPiece, side: Side) -> u8 {
+    let code = match piece {
+        ChessPiece::Pawn => 1,
+        ChessPiece::Knight => 2,
+        ChessPiece::Bishop => 3,
+        ChessPiece::Rook => 4,
+        ChessPiece::Queen => 5,
+        ChessPiece::King => 6
+    };
+    match side {
+        Side::White => code,
+        Side::Black => code + 8
+    }
+}
+
+fn piece_from_letter(letter: char) -> Option<ChessPiece> {
+    match letter {
+        'K' => Some(ChessPiece::King),
+        'Q' => Some(ChessPiece::Queen),
+        'R' => Some(ChessPiece::Rook),
+        'B' => Some(ChessPiece::Bishop),
+        'N' => Some(ChessPiece::Knight),
+        'P' => Some(ChessPiece::Pawn),
+        _ => None
+    }
+}
+
+//Numbering of the squares the leading group can stand on once the board is turned so
+//its first piece is in the a1-d1-d4 triangle
+struct SquareMaps {
+    //The 28 squares below the a1-h8 diagonal
+    below_diagonal: [usize; 64],
+    //The triangle, the squares below the diagonal first
+    triangle: [usize; 64],
+    //Both kings: first the triangle code of one, then the square of the other
+    kings: [[usize; 64]; 10]
+}
+
+fn square_maps() -> &'static SquareMaps {
+    static MAPS: OnceLock<SquareMaps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut below_diagonal = [0; 64];
+        for (code, square) in (0..64).filter(|square| off_diagonal(*square) < 0).enumerate() {
+            below_diagonal[square] = code;
+        }
+
+        let mut triangle = [0; 64];
+        let in_triangle = |square: &usize| file_of(*square) <= 3 && off_diagonal(*square) <= 0;
+        let below = (0..28).filter(in_triangle).filter(|square| off_diagonal(*square) < 0);
+        let on_diagonal = (0..28).filter(in_triangle).filter(|square| off_diagonal(*square) == 0);
+        for (code, square) in below.chain(on_diagonal).enumerate() {
+            triangle[square] = code;
+        }
+
+        //Kings touching each other are left out, and with the first king on the diagonal
+        //the other is kept below it. Both on the diagonal come last
+        let mut kings = [[0; 64]; 10];
+        let mut both_on_diagonal = vec![];
+        let mut code = 0;
+        for (first_code, first_codes) in kings.iter_mut().enumerate() {
+            for first in (0..28).filter(|square| in_triangle(square) && triangle[*square] == first_code) {
+                for (second, second_code) in first_codes.iter_mut().enumerate() {
+                    let touching = file_of(first).abs_diff(file_of(second)) <= 1
+                        && rank_of(first).abs_diff(rank_of(second)) <= 1;
+                    if touching || (off_diagonal(first) == 0 && off_diagonal(second) > 0) {continue;}
+                    if off_diagonal(first) == 0 && off_diagonal(second) == 0 {
+                        both_on_diagonal.push((first_code, second));
+                    } else {
+                        *second_code = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (first_code, second) in both_on_diagonal {
+            kings[first_code][second] = code;
+            code += 1;
+        }
+        SquareMaps { below_diagonal, triangle, kings }
+    })
+}
+
+//Material of a table, with the side the file name lists first as white
+struct Material {
+    piece_count: usize,
+    //Both sides have the same pieces
+    symmetric: bool,
+    //Some piece other than a king is the only one of its kind on its side
+    unique: bool,
+    codes: Vec<u8>
+}
+
+impl Material {
+    //`None` for names that are not an endgame, or have pawns
+    fn from_name(name: &str) -> Option<Material> {
+        let (white, black) = name.split_once('v')?;
+        let mut codes = vec![];
+        let mut unique = false;
+        for (letters, side) in [(white, Side::White), (black, Side::Black)] {
+            let pieces: Vec<ChessPiece> = letters.chars().map(piece_from_letter).collect::<Option<_>>()?;
+            if pieces.iter().filter(|piece| **piece == ChessPiece::King).count() != 1 {return None;}
+            if pieces.contains(&ChessPiece::Pawn) {return None;}
+            for piece in &pieces {
+                unique |= *piece != ChessPiece::King && pieces.iter().filter(|other| *other == piece).count() == 1;
+                codes.push(piece_code(*piece, side));
+            }
+        }
+        let mut white_codes: Vec<u8> = codes.iter().filter(|code| **code < 8).copied().collect();
+        let mut black_codes: Vec<u8> = codes.iter().filter(|code| **code > 8).map(|code| code - 8).collect();
+        white_codes.sort_unstable();
+        black_codes.sort_unstable();
+        Some(Material { piece_count: codes.len(), symmetric: white_codes == black_codes, unique, codes })
+    }
+}
+
+//How a table numbers positions. The leading group is placed with the square maps, every
+//later group of like pieces as a combination of the squares still free
+struct Layout {
+    pieces: Vec<u8>,
+    group_len: Vec<usize>,
+    //Multiplier of each group's number, the last entry is the number of positions
+    group_factor: Vec<u64>,
+    unique: bool
+}
+
+impl Layout {
+    fn new(pieces: Vec<u8>, order: usize, unique: bool) -> io::Result<Layout> {
+        let mut first_len: i32 = if unique {3} else {2};
+        let mut group_len = vec![1];
+        for i in 1..pieces.len() {
+            first_len -= 1;
+            match group_len.last_mut() {
+                Some(len) if first_len > 0 || pieces[i] == pieces[i - 1] => *len += 1,
+                _ => group_len.push(1)
+            }
+        }
+        let groups = group_len.len();
+        if order >= groups {return Err(invalid("bad group order"));}
+
+        //Groups are multiplied out in the order the file gives, the leading one at `order`
+        let mut group_factor = vec![0; groups + 1];
+        let mut free_squares = 64 - group_len[0];
+        let mut next = 1;
+        let mut factor = 1;
+        for k in 0..groups {
+            if k == order {
+                group_factor[0] = factor;
+                factor *= if unique {UNIQUE_PLACEMENTS} else {KING_PLACEMENTS};
+            } else {
+                group_factor[next] = factor;
+                factor *= binomial(group_len[next], free_squares);
+                free_squares -= group_len[next];
+                next += 1;
+            }
+        }
+        group_factor[groups] = factor;
+        Ok(Layout { pieces, group_len, group_factor, unique })
+    }
+
+    fn size(&self) -> u64 {
+        self.group_factor[self.group_len.len()]
+    }
+
+    //Number of a position given as piece codes and squares in the table's colours,
+    //`None` when those are not the table's pieces
+    fn index(&self, mut pieces: Vec<(u8, usize)>) -> Option<u64> {
+        if pieces.len() != self.pieces.len() {return None;}
+        for i in 0..pieces.len() {
+            let same = (i..pieces.len()).find(|j| pieces[*j].0 == self.pieces[i])?;
+            pieces.swap(i, same);
+        }
+        let mut squares: Vec<usize> = pieces.iter().map(|(_, square)| *square).collect();
+
+        //Turned so the first piece is in the a1-d1-d4 triangle, and the first piece of the
+        //leading group off the diagonal below it
+        if file_of(squares[0]) > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 7);
+        }
+        if rank_of(squares[0]) > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 56);
+        }
+        if let Some(first_off) = (0..self.group_len[0]).find(|i| off_diagonal(squares[*i]) != 0) {
+            if off_diagonal(squares[first_off]) > 0 {
+                squares[first_off..].iter_mut().for_each(|square| *square = flip_diagonal(*square));
+            }
+        }
+
+        let maps = square_maps();
+        let mut index = if self.unique {
+            let (first, second, third) = (squares[0], squares[1], squares[2]);
+            let adjust1 = (second > first) as usize;
+            let adjust2 = (third > first) as usize + (third > second) as usize;
+            let index = if off_diagonal(first) != 0 {
+                (maps.triangle[first] * 63 + second - adjust1) * 62 + third - adjust2
+            } else if off_diagonal(second) != 0 {
+                (6 * 63 + rank_of(first) * 28 + maps.below_diagonal[second]) * 62 + third - adjust2
+            } else if off_diagonal(third) != 0 {
+                6 * 63 * 62 + 4 * 28 * 62 + rank_of(first) * 7 * 28
+                    + (rank_of(second) - adjust1) * 28 + maps.below_diagonal[third]
+            } else {
+                6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(first) * 7 * 6
+                    + (rank_of(second) - adjust1) * 6 + rank_of(third) - adjust2
+            };
+            index as u64
+        } else {
+            maps.kings[maps.triangle[squares[0]]][squares[1]] as u64
+        };
+        index *= self.group_factor[0];
+
+        //Squares already taken by earlier groups are skipped in the numbering
+        let mut start = self.group_len[0];
+        for group in 1..self.group_len.len() {
+            let end = start + self.group_len[group];
+            squares[start..end].sort_unstable();
+            let mut combination = 0;
+            for i in start..end {
+                let adjust = squares[..start].iter().filter(|earlier| squares[i] > **earlier).count();
+                combination += binomial(i - start + 1, squares[i] - adjust);
+            }
+            index += combination * self.group_factor[group];
+            start = end;
+        }
+        Some(index).filter(|index| *index < self.size())
+    }
+}
+
+//Reads the header fields in file order
+struct Reader<'a> {
+    bytes: &'a [u8],
+    at: usize
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> io::Result<u8> {
+        let byte = *self.bytes.get(self.at).ok_or_else(|| invalid("truncated table"))?;
+        self.at += 1;
+        Ok(byte)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes([self.byte()?, self.byte()?, self.byte()?, self.byte()?]))
+    }
+
+    fn skip(&mut self, len: usize) {
+        self.at += len;
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.at = self.at.div_ceil(alignment) * alignment;
+    }
+}
+
+fn read_le(bytes: &[u8], at: usize, len: usize) -> Option<u32> {
+    let bytes = bytes.get(at..at + len)?;
+    Some(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32))
+}
+
+//Compressed data is read a word at a time, a block near the end of the file may read past it
+fn read_be_padded(bytes: &[u8], at: usize, len: usize) -> u64 {
+    (at..at + len).fold(0, |value, i| value << 8 | bytes.get(i).copied().unwrap_or(0) as u64)
+}
+
+//Values of one side to move, Huffman coded over symbols that each stand for a run of values.
+//Everything but the decoding tables is an offset into the file
+struct SideTable {
+    layout: Layout,
+    flags: u8,
+    //Also the value of a table that has only one
+    min_symbol_len: u32,
+    //Smallest code of each length, left-aligned, shortest length first
+    base: Vec<u64>,
+    lowest_symbol: usize,
+    //Values each symbol stands for, less one
+    symbol_values: Vec<u32>,
+    tree: usize,
+    block_size: usize,
+    span: u64,
+    sparse_index: usize,
+    sparse_index_len: usize,
+    block_lengths: usize,
+    block_lengths_len: usize,
+    num_blocks: usize,
+    data: usize,
+    //Offsets of the DTZ values of a win, a loss, a cursed win and a blessed loss
+    dtz_map: [usize; 4]
+}
+
+fn tree_entry(bytes: &[u8], tree: usize, symbol: usize) -> Option<(usize, usize)> {
+    let entry = bytes.get(tree + 3 * symbol..tree + 3 * symbol + 3)?;
+    let left = ((entry[1] as usize & 0xF) << 8) | entry[0] as usize;
+    let right = ((entry[2] as usize) << 4) | (entry[1] as usize >> 4);
+    Some((left, right))
+}
+
+fn count_symbol_values(bytes: &[u8], tree: usize, symbol: usize, values: &mut [u32], visited: &mut [bool]) -> io::Result<u32> {
+    visited[symbol] = true;
+    let (left, right) = tree_entry(bytes, tree, symbol).ok_or_else(|| invalid("truncated symbol tree"))?;
+    if right == LEAF {return Ok(0);}
+    for child in [left, right] {
+        if child >= values.len() {return Err(invalid("bad symbol tree"));}
+        if !visited[child] {
+            values[child] = count_symbol_values(bytes, tree, child, values, visited)?;
+        }
+    }
+    Ok(values[left] + values[right] + 1)
+}
+
+impl SideTable {
+    fn read_sizes(reader: &mut Reader, layout: Layout) -> io::Result<SideTable> {
+        let mut table = SideTable {
+            layout,
+            flags: reader.byte()?,
+            min_symbol_len: 0,
+            base: vec![],
+            lowest_symbol: 0,
+            symbol_values: vec![],
+            tree: 0,
+            block_size: 0,
+            span: 0,
+            sparse_index: 0,
+            sparse_index_len: 0,
+            block_lengths: 0,
+            block_lengths_len: 0,
+            num_blocks: 0,
+            data: 0,
+            dtz_map: [0; 4]
+        };
+        if table.flags & SINGLE_VALUE != 0 {
+            table.min_symbol_len = reader.byte()? as u32;
+            return Ok(table);
+        }
+
+        let (block_bits, span_bits) = (reader.byte()?, reader.byte()?);
+        if block_bits > 31 || span_bits > 31 {return Err(invalid("bad block size"));}
+        table.block_size = 1 << block_bits;
+        table.span = 1 << span_bits;
+        table.sparse_index_len = table.layout.size().div_ceil(table.span) as usize;
+        let padding = reader.byte()? as usize;
+        table.num_blocks = reader.u32()? as usize;
+        table.block_lengths_len = table.num_blocks + padding;
+
+        let max_symbol_len = reader.byte()? as u32;
+        table.min_symbol_len = reader.byte()? as u32;
+        if table.min_symbol_len == 0 || max_symbol_len < table.min_symbol_len || max_symbol_len > 32 {
+            return Err(invalid("bad code lengths"));
+        }
+        table.lowest_symbol = reader.at;
+        let lengths = (max_symbol_len - table.min_symbol_len + 1) as usize;
+        let mut lowest = vec![0; lengths];
+        for lowest in lowest.iter_mut() {
+            *lowest = reader.u16()? as u64;
+        }
+        table.base = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let base = (table.base[i + 1] + lowest[i]).checked_sub(lowest[i + 1]).ok_or_else(|| invalid("bad code lengths"))?;
+            table.base[i] = base / 2;
+        }
+        for (i, base) in table.base.iter_mut().enumerate() {
+            *base <<= 64 - i as u32 - table.min_symbol_len;
+        }
+
+        let symbol_count = reader.u16()? as usize;
+        table.tree = reader.at;
+        reader.skip(3 * symbol_count + (symbol_count & 1));
+        let mut values = vec![0; symbol_count];
+        let mut visited = vec![false; symbol_count];
+        for symbol in 0..symbol_count {
+            if !visited[symbol] {
+                values[symbol] = count_symbol_values(reader.bytes, table.tree, symbol, &mut values, &mut visited)?;
+            }
+        }
+        table.symbol_values = values;
+        Ok(table)
+    }
+
+    //Stored value of a position number, `None` when the file is cut short or corrupt
+    fn value(&self, bytes: &[u8], index: u64) -> Option<u32> {
+        if self.flags & SINGLE_VALUE != 0 {return Some(self.min_symbol_len);}
+
+        //The sparse index points at a block and an offset for the middle of each span
+        let entry = (index / self.span) as usize;
+        if entry >= self.sparse_index_len {return None;}
+        let mut block = read_le(bytes, self.sparse_index + 6 * entry, 4)? as usize;
+        let mut offset = read_le(bytes, self.sparse_index + 6 * entry + 4, 2)? as i64
+            + (index % self.span) as i64 - (self.span / 2) as i64;
+        let block_length = |block: usize| if block < self.block_lengths_len {
+            read_le(bytes, self.block_lengths + 2 * block, 2).map(|length| length as i64)
+        } else {
+            None
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+        //Blocks fill the file up to its end, a shorter file was cut off
+        if block >= self.num_blocks || self.data + (block + 1) * self.block_size > bytes.len() {return None;}
+
+        //Symbols are decoded until the one holding the value at `offset`
+        let mut at = self.data + block * self.block_size;
+        let mut buffer = read_be_padded(bytes, at, 8);
+        at += 8;
+        let mut buffer_bits = 64;
+        let mut symbol;
+        loop {
+            let mut len = 0;
+            while buffer < *self.base.get(len)? {
+                len += 1;
+            }
+            symbol = ((buffer - self.base[len]) >> (64 - len as u32 - self.min_symbol_len)) as usize;
+            symbol += read_le(bytes, self.lowest_symbol + 2 * len, 2)? as usize;
+            let values = *self.symbol_values.get(symbol)? as i64 + 1;
+            if offset < values {break;}
+            offset -= values;
+            let len = len as u32 + self.min_symbol_len;
+            buffer <<= len;
+            buffer_bits -= len;
+            if buffer_bits <= 32 {
+                buffer_bits += 32;
+                buffer |= read_be_padded(bytes, at, 4) << (64 - buffer_bits);
+                at += 4;
+            }
+        }
+
+        //Then down the pairs to the single value
+        while self.symbol_values[symbol] != 0 {
+            let (left, right) = tree_entry(bytes, self.tree, symbol)?;
+            let left_values = *self.symbol_values.get(left)? as i64 + 1;
+            if offset < left_values {
+                symbol = left;
+            } else {
+                offset -= left_values;
+                symbol = right;
+            }
+            if symbol >= self.symbol_values.len() {return None;}
+        }
+        tree_entry(bytes, self.tree, symbol).map(|(left, _)| left as u32)
+    }
+
+    //Plies to zeroing from a stored DTZ value, given the position's WDL value from -2 to 2
+    fn dtz_plies(&self, bytes: &[u8], value: u32, wdl: i32) -> Option<i32> {
+        let mut plies = value as i32;
+        if self.flags & MAPPED != 0 {
+            let start = self.dtz_map[DTZ_MAP_OF_WDL[(wdl + 2) as usize]];
+            plies = if self.flags & WIDE != 0 {
+                read_le(bytes, start + 2 * value as usize, 2)? as i32
+            } else {
+                *bytes.get(start + value as usize)? as i32
+            };
+        }
+        let in_moves = match wdl {
+            2 => self.flags & WIN_PLIES == 0,
+            -2 => self.flags & LOSS_PLIES == 0,
+            _ => true
+        };
+        if in_moves {plies *= 2;}
+        Some(plies + 1)
+    }
+}
+
+struct TableFile {
+    bytes: Vec<u8>,
+    //One per side to move, WDL tables of unequal material have both
+    sides: Vec<SideTable>
+}
+
+impl TableFile {
+    fn read(bytes: Vec<u8>, dtz: bool, material: &Material) -> io::Result<TableFile> {
+        let magic = if dtz {DTZ_MAGIC} else {WDL_MAGIC};
+        if bytes.len() < 5 || bytes[..4] != magic {return Err(invalid("not a Syzygy table"));}
+        let mut reader = Reader { bytes: &bytes, at: 4 };
+        if reader.byte()? & HAS_PAWNS != 0 {return Err(invalid("tables with pawns are not supported"));}
+
+        let side_count = if dtz || material.symmetric {1} else {2};
+        let order = reader.byte()?;
+        let mut pieces = vec![vec![]; side_count];
+        for _ in 0..material.piece_count {
+            let byte = reader.byte()?;
+            for (side, side_pieces) in pieces.iter_mut().enumerate() {
+                side_pieces.push(if side == 0 {byte & 0xF} else {byte >> 4});
+            }
+        }
+        let mut expected = material.codes.clone();
+        expected.sort_unstable();
+        let mut layouts = vec![];
+        for (side, side_pieces) in pieces.into_iter().enumerate() {
+            let mut sorted = side_pieces.clone();
+            sorted.sort_unstable();
+            if sorted != expected {return Err(invalid("pieces do not match the file name"));}
+            let order = if side == 0 {order & 0xF} else {order >> 4};
+            layouts.push(Layout::new(side_pieces, order as usize, material.unique)?);
+        }
+        reader.align(2);
+
+        let mut sides = vec![];
+        for layout in layouts {
+            sides.push(SideTable::read_sizes(&mut reader, layout)?);
+        }
+        if dtz {
+            let side = &mut sides[0];
+            if side.flags & MAPPED != 0 {
+                for start in side.dtz_map.iter_mut() {
+                    if side.flags & WIDE != 0 {
+                        reader.align(2);
+                        let len = reader.u16()? as usize;
+                        *start = reader.at;
+                        reader.skip(2 * len);
+                    } else {
+                        let len = reader.byte()? as usize;
+                        *start = reader.at;
+                        reader.skip(len);
+                    }
+                }
+            }
+            reader.align(2);
+        }
+        for side in sides.iter_mut() {
+            side.sparse_index = reader.at;
+            reader.skip(6 * side.sparse_index_len);
+        }
+        for side in sides.iter_mut() {
+            side.block_lengths = reader.at;
+            reader.skip(2 * side.block_lengths_len);
+        }
+        for side in sides.iter_mut() {
+            reader.align(64);
+            side.data = reader.at;
+            reader.skip(side.num_blocks * side.block_size);
+        }
+        Ok(TableFile { bytes, sides })
+    }
+}
+
+//A file read the first time it is probed, a broken one is reported once and left alone
+struct LazyFile {
+    path: PathBuf,
+    dtz: bool,
+    file: OnceLock<Option<TableFile>>
+}
+
+impl LazyFile {
+    fn new(path: PathBuf, dtz: bool) -> LazyFile {
+        LazyFile { path, dtz, file: OnceLock::new() }
+    }
+
+    fn get(&self, material: &Material) -> Option<&TableFile> {
+        self.file.get_or_init(|| {
+            fs::read(&self.path)
+                .and_then(|bytes| TableFile::read(bytes, self.dtz, material))
+                .map_err(|error| println!("Could not read Syzygy table {}: {}", self.path.display(), error))
+                .ok()
+        }).as_ref()
+    }
+}
+
+//A position's value as a table stores it
+enum Stored<'a> {
+    Value(u32, &'a SideTable, &'a [u8]),
+    //The DTZ file only holds the other side to move
+    OtherSide
+}
+
+struct Entry {
+    material: Material,
+    wdl: LazyFile,
+    dtz: Option<LazyFile>
+}
+
+/// Syzygy WDL (`.rtbw`) and DTZ (`.rtbz`) tables of endgames without pawns.
+///
+/// Pawns here step straight ahead and never promote, so tables with pawns don't apply and
+/// are skipped. Every other piece moves as in standard chess and there is no fifty-move rule,
+/// which only makes cursed wins and blessed losses plain wins and losses.
+#[derive(Default)]
+pub struct SyzygyTables {
+    entries: HashMap<String, Entry>,
+    skipped_pawn_tables: usize
+}
+
+//Stockfish's convention: -2 loss, -1 blessed loss, 0 draw, 1 cursed win, 2 win
+fn wdl_of(value: i32) -> Wdl {
+    match value.signum() {
+        1 => Wdl::Win,
+        -1 => Wdl::Loss,
+        _ => Wdl::Draw
+    }
+}
+
+//DTZ of a position whose best move zeroes, from its WDL value
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        2 => 1,
+        1 => 101,
+        -1 => -101,
+        -2 => -1,
+        _ => 0
+    }
+}
+
+impl SyzygyTables {
+    /// Finds the tables in `directory`, they are only read once probed.
+    pub fn open(directory: &Path) -> io::Result<SyzygyTables> {
+        let mut tables = SyzygyTables::default();
+        for dir_entry in fs::read_dir(directory)? {
+            let path = dir_entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(WDL_EXTENSION) {continue;}
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue
+            };
+            let material = match Material::from_name(&name) {
+                Some(material) if material.piece_count <= SYZYGY_MAX_PIECES => material,
+                Some(_) => continue,
+                None => {
+                    if name.contains('P') {tables.skipped_pawn_tables += 1;}
+                    continue;
+                }
+            };
+            let dtz_path = path.with_extension(DTZ_EXTENSION);
+            let dtz = dtz_path.is_file().then(|| LazyFile::new(dtz_path, true));
+            tables.entries.insert(name, Entry { material, wdl: LazyFile::new(path, false), dtz });
+        }
+        Ok(tables)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Tables found with pawns in them, which don't apply here.
+    pub fn skipped_pawn_tables(&self) -> usize {
+        self.skipped_pawn_tables
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.entries.values().map(|entry| entry.material.piece_count).max().unwrap_or(0)
+    }
+
+    /// Names of the endgames covered, sorted.
+    pub fn names(&self) -> Vec<&String> {
+        let mut names: Vec<&String> = self.entries.keys().collect();
+        names.sort();
+        names
+    }
+
+    //The table for sorted pieces, and whether colours are swapped to read it: when black
+    //has the pieces the file name lists first, or both have the same and black is to move
+    fn find(&self, pieces: &[(ChessPiece, Side, (i8, i8))], whose_turn: Side) -> Option<(&Entry, bool)> {
+        let name = material_name(pieces);
+        if let Some(entry) = self.entries.get(&name) {
+            return Some((entry, entry.material.symmetric && whose_turn == Side::Black));
+        }
+        let (white, black) = name.split_once('v')?;
+        self.entries.get(&format!("{}v{}", black, white)).map(|entry| (entry, true))
+    }
+
+    //Holds the position's material, bare kings are left to the caller
+    fn covers(&self, board: &Board, whose_turn: Side) -> bool {
+        let mut pieces = board.pieces();
+        sort_pieces(&mut pieces);
+        self.find(&pieces, whose_turn).is_some()
+    }
+
+    //Stored value of the position and the table it came from, from the DTZ file when `dtz`
+    fn table_value(&self, board: &Board, whose_turn: Side, dtz: bool) -> Option<Stored<'_>> {
+        let mut pieces = board.pieces();
+        sort_pieces(&mut pieces);
+        let (entry, flip) = self.find(&pieces, whose_turn)?;
+        let stm = ((whose_turn == Side::Black) != flip) as usize;
+        let file = match dtz {
+            true => entry.dtz.as_ref()?.get(&entry.material)?,
+            false => entry.wdl.get(&entry.material)?
+        };
+        let side = &file.sides[stm % file.sides.len()];
+        if dtz && (side.flags & STORED_SIDE) as usize != stm && !entry.material.symmetric {
+            return Some(Stored::OtherSide);
+        }
+
+        let table_pieces = pieces.iter()
+            .map(|(piece, side, (x, y))| {
+                let black = (*side == Side::Black) != flip;
+                let code = piece_code(*piece, if black {Side::Black} else {Side::White});
+                let square = ((7 - y) * 8 + x) as usize;
+                (code, if flip {square ^ 56} else {square})
+            })
+            .collect();
+        let index = side.layout.index(table_pieces)?;
+        Some(Stored::Value(side.value(&file.bytes, index)?, side, &file.bytes))
+    }
+
+    fn probe_wdl_table(&self, board: &Board, whose_turn: Side) -> Option<i32> {
+        if board.piece_count() == 2 {return Some(0);}
+        match self.table_value(board, whose_turn, false)? {
+            Stored::Value(value, _, _) => Some(value as i32 - 2),
+            Stored::OtherSide => None
+        }
+    }
+
+    //The tables store whatever compresses best where a capture is the best move, so
+    //captures are searched. Also tells whether a capture is the best move
+    fn search_wdl(&self, board: &mut Board, whose_turn: Side) -> Option<(i32, bool)> {
+        let moves = board.get_legal_moves_for_side(whose_turn);
+        let mut best = -2;
+        let mut captures = 0;
+        for l_move in &moves {
+            if board.captured_piece(l_move).is_none() {continue;}
+            captures += 1;
+            board.make_move(l_move);
+            let value = self.search_wdl(board, swap_turn(whose_turn));
+            board.unmake_move(l_move);
+            let value = -value?.0;
+            if value > best {
+                if value == 2 {return Some((value, true));}
+                best = value;
+            }
+        }
+        let only_captures = captures > 0 && captures == moves.len();
+        let value = if only_captures {best} else {self.probe_wdl_table(board, whose_turn)?};
+        if best >= value {
+            return Some((best, best > 0 || only_captures));
+        }
+        Some((value, false))
+    }
+
+    //Signed DTZ, a hundred plies longer for cursed wins and blessed losses
+    fn probe_dtz(&self, board: &mut Board, whose_turn: Side) -> Option<i32> {
+        let (wdl, capture_is_best) = self.search_wdl(board, whose_turn)?;
+        if wdl == 0 {return Some(0);}
+        if capture_is_best {return Some(dtz_before_zeroing(wdl));}
+        if let Stored::Value(value, side, bytes) = self.table_value(board, whose_turn, true)? {
+            let cursed = if wdl.abs() == 1 {100} else {0};
+            return Some((side.dtz_plies(bytes, value, wdl)? + cursed) * wdl.signum());
+        }
+
+        //Stored for the other side to move, so one move deeper
+        let opponent = swap_turn(whose_turn);
+        let mut best = i32::MAX;
+        for l_move in board.get_legal_moves_for_side(whose_turn) {
+            let zeroing = board.captured_piece(&l_move).is_some();
+            board.make_move(&l_move);
+            let dtz = match zeroing {
+                true => self.search_wdl(board, opponent).map(|(value, _)| -dtz_before_zeroing(value)),
+                false => self.probe_dtz(board, opponent).map(|dtz| -dtz)
+            };
+            let mates = dtz == Some(1) && board.is_in_check(opponent) && board.get_legal_moves_for_side(opponent).is_empty();
+            board.unmake_move(&l_move);
+
+            let mut dtz = dtz?;
+            if mates {best = 1;}
+            if !zeroing {dtz += dtz.signum();}
+            if dtz < best && dtz.signum() == wdl.signum() {best = dtz;}
+        }
+        Some(if best == i32::MAX {-1} else {best})
+    }
+
+    /// Win, draw or loss for the side to move, `None` when no table holds the material.
+    pub fn probe_wdl(&self, board: &Board, whose_turn: Side) -> Option<Wdl> {
+        if !self.covers(board, whose_turn) {return None;}
+        self.search_wdl(&mut board.clone(), whose_turn).map(|(value, _)| wdl_of(value))
+    }
+
+    /// Result for the side to move, `None` when no table holds the material or its DTZ
+    /// file is missing. Distances read from files that store moves rather than plies
+    /// can be one ply long, cursed wins and blessed losses are a hundred plies longer.
+    pub fn probe(&self, board: &Board, whose_turn: Side) -> Option<Probe> {
+        if !self.covers(board, whose_turn) {return None;}
+        let mut board = board.clone();
+        if board.get_legal_moves_for_side(whose_turn).is_empty() {
+            let wdl = if board.is_in_check(whose_turn) {Wdl::Loss} else {Wdl::Draw};
+            return Some(Probe { wdl, dtz: 0 });
+        }
+        let dtz = self.probe_dtz(&mut board, whose_turn)?;
+        Some(Probe { wdl: wdl_of(dtz), dtz: dtz.unsigned_abs() as u16 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    use super::*;
+    use super::super::Tablebases;
+    use super::super::tests::{king_and_piece_board, king_and_queen_board, tablebases};
+
+    //Most tests write their own files the way the Syzygy generator lays them out, from the
+    //generated tables, and read them back. Real tables are checked when they are in place
+    const REAL_TABLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/chess/tablebase/testdata");
+    const BLOCK_BITS: u8 = 5;
+    const SPAN_BITS: u8 = 6;
+    const KQVK: [u8; 3] = [6, 5, 14];
+    const KNVK: [u8; 3] = [6, 2, 14];
+
+    //Values of one side to move, compressed
+    enum Packed {
+        Single(u8),
+        Blocks {
+            min_len: usize,
+            //Lowest symbol number of each code length, shortest first
+            lowest: Vec<u16>,
+            tree: Vec<(usize, usize)>,
+            sparse: Vec<(u32, u16)>,
+            block_lengths: Vec<u16>,
+            data: Vec<u8>
+        }
+    }
+
+    fn huffman_lengths(frequency: &[u64]) -> Vec<usize> {
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = frequency.iter()
+            .enumerate()
+            .map(|(symbol, frequency)| Reverse((*frequency, symbol)))
+            .collect();
+        let mut parent = vec![usize::MAX; frequency.len()];
+        while heap.len() > 1 {
+            let Reverse((first_frequency, first)) = heap.pop().unwrap();
+            let Reverse((second_frequency, second)) = heap.pop().unwrap();
+            parent.push(usize::MAX);
+            parent[first] = parent.len() - 1;
+            parent[second] = parent.len() - 1;
+            heap.push(Reverse((first_frequency + second_frequency, parent.len() - 1)));
+        }
+        (0..frequency.len())
+            .map(|mut node| {
+                let mut len = 0;
+                while parent[node] != usize::MAX {
+                    node = parent[node];
+                    len += 1;
+                }
+                len
+            })
+            .collect()
+    }
+
+    fn pack(values: &[u8]) -> Packed {
+        let mut distinct = values.to_vec();
+        distinct.sort_unstable();
+        distinct.dedup();
+        if distinct.len() == 1 {return Packed::Single(distinct[0]);}
+
+        //A symbol per value, then a few rounds pairing the most common neighbours
+        let mut tree: Vec<(usize, usize)> = distinct.iter().map(|value| (*value as usize, LEAF)).collect();
+        let mut expands_to = vec![1; tree.len()];
+        let mut stream: Vec<usize> = values.iter().map(|value| distinct.binary_search(value).unwrap()).collect();
+        for _ in 0..3 {
+            let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+            for pair in stream.windows(2) {
+                *counts.entry((pair[0], pair[1])).or_default() += 1;
+            }
+            let (pair, count) = counts.into_iter().max_by_key(|(pair, count)| (*count, Reverse(*pair))).unwrap();
+            if count < 2 {break;}
+            tree.push(pair);
+            expands_to.push(expands_to[pair.0] + expands_to[pair.1]);
+            let mut paired = Vec::with_capacity(stream.len());
+            let mut i = 0;
+            while i < stream.len() {
+                if i + 1 < stream.len() && (stream[i], stream[i + 1]) == pair {
+                    paired.push(tree.len() - 1);
+                    i += 2;
+                } else {
+                    paired.push(stream[i]);
+                    i += 1;
+                }
+            }
+            stream = paired;
+        }
+
+        //Canonical codes, the longest ones get the lowest symbol numbers
+        let mut frequency = vec![0; tree.len()];
+        for symbol in &stream {
+            frequency[*symbol] += 1;
+        }
+        let lengths = huffman_lengths(&frequency);
+        let mut order: Vec<usize> = (0..tree.len()).collect();
+        order.sort_by_key(|symbol| (Reverse(lengths[*symbol]), *symbol));
+        let mut number = vec![0; tree.len()];
+        for (n, symbol) in order.iter().enumerate() {
+            number[*symbol] = n;
+        }
+        let min_len = *lengths.iter().min().unwrap();
+        let max_len = *lengths.iter().max().unwrap();
+        let count = |len: usize| lengths.iter().filter(|symbol_len| **symbol_len == len).count();
+        let mut lowest = vec![0; max_len + 2];
+        let mut base = vec![0u64; max_len + 2];
+        for len in (min_len..max_len).rev() {
+            lowest[len] = lowest[len + 1] + count(len + 1);
+            base[len] = (base[len + 1] + count(len + 1) as u64) / 2;
+        }
+
+        //Codes fill blocks of a fixed size, a symbol never straddles two
+        let block_size = 1 << BLOCK_BITS;
+        let mut data = vec![];
+        let mut block_lengths = vec![];
+        let mut block_starts = vec![];
+        let mut bits: Vec<bool> = vec![];
+        let mut block_values = 0;
+        let mut value_index = 0;
+        let flush = |bits: &mut Vec<bool>, block_values: &mut usize, data: &mut Vec<u8>, block_lengths: &mut Vec<u16>| {
+            bits.resize(block_size * 8, false);
+            data.extend(bits.chunks(8).map(|byte| byte.iter().fold(0, |byte, bit| byte << 1 | *bit as u8)));
+            block_lengths.push((*block_values - 1) as u16);
+            bits.clear();
+            *block_values = 0;
+        };
+        for symbol in &stream {
+            let len = lengths[*symbol];
+            if bits.len() + len > block_size * 8 {
+                flush(&mut bits, &mut block_values, &mut data, &mut block_lengths);
+            }
+            if block_values == 0 {block_starts.push(value_index);}
+            let code = base[len] + (number[*symbol] - lowest[len]) as u64;
+            bits.extend((0..len).rev().map(|bit| code >> bit & 1 == 1));
+            block_values += expands_to[*symbol];
+            value_index += expands_to[*symbol];
+        }
+        flush(&mut bits, &mut block_values, &mut data, &mut block_lengths);
+
+        //Block and offset of the middle of every span
+        let span = 1 << SPAN_BITS;
+        let sparse = (0..values.len().div_ceil(span))
+            .map(|entry| {
+                let middle = entry * span + span / 2;
+                let block = block_starts.partition_point(|start| *start <= middle) - 1;
+                (block as u32, (middle - block_starts[block]) as u16)
+            })
+            .collect();
+        let renumbered = order.iter()
+            .map(|symbol| match tree[*symbol] {
+                (value, LEAF) => (value, LEAF),
+                (left, right) => (number[left], number[right])
+            })
+            .collect();
+        Packed::Blocks {
+            min_len,
+            lowest: (min_len..=max_len).map(|len| lowest[len] as u16).collect(),
+            tree: renumbered,
+            sparse,
+            block_lengths,
+            data
+        }
+    }
+
+    fn align(bytes: &mut Vec<u8>, alignment: usize) {
+        bytes.resize(bytes.len().div_ceil(alignment) * alignment, 0);
+    }
+
+    //DTZ files hold one side to move, and a map from stored values to distances when
+    //`dtz_map` gives the values of a win, a loss, a cursed win and a blessed loss
+    fn table_file(dtz: bool, pieces: &[u8], sides: &[(u8, Packed)], dtz_map: Option<(&[Vec<u16>; 4], bool)>) -> Vec<u8> {
+        let mut bytes = if dtz {DTZ_MAGIC} else {WDL_MAGIC}.to_vec();
+        bytes.push(1);
+        bytes.push(0);
+        bytes.extend(pieces.iter().map(|piece| piece | piece << 4));
+        align(&mut bytes, 2);
+        for (flags, packed) in sides {
+            match packed {
+                Packed::Single(value) => bytes.extend([flags | SINGLE_VALUE, *value]),
+                Packed::Blocks { min_len, lowest, tree, data, .. } => {
+                    bytes.extend([*flags, BLOCK_BITS, SPAN_BITS, 0]);
+                    bytes.extend((data.len() as u32 >> BLOCK_BITS).to_le_bytes());
+                    bytes.extend([(min_len + lowest.len() - 1) as u8, *min_len as u8]);
+                    bytes.extend(lowest.iter().flat_map(|lowest| lowest.to_le_bytes()));
+                    bytes.extend((tree.len() as u16).to_le_bytes());
+                    for (left, right) in tree {
+                        bytes.extend([*left as u8, ((left >> 8) | (right & 0xF) << 4) as u8, (right >> 4) as u8]);
+                    }
+                    if tree.len() % 2 == 1 {bytes.push(0);}
+                }
+            }
+        }
+        if let Some((map, wide)) = dtz_map {
+            for values in map {
+                if wide {
+                    align(&mut bytes, 2);
+                    bytes.extend((values.len() as u16).to_le_bytes());
+                    bytes.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+                } else {
+                    bytes.push(values.len() as u8);
+                    bytes.extend(values.iter().map(|value| *value as u8));
+                }
+            }
+        }
+        if dtz {align(&mut bytes, 2);}
+        for (_, packed) in sides {
+            if let Packed::Blocks { sparse, .. } = packed {
+                for (block, offset) in sparse {
+                    bytes.extend(block.to_le_bytes());
+                    bytes.extend(offset.to_le_bytes());
+                }
+            }
+        }
+        for (_, packed) in sides {
+            if let Packed::Blocks { block_lengths, .. } = packed {
+                bytes.extend(block_lengths.iter().flat_map(|length| length.to_le_bytes()));
+            }
+        }
+        for (_, packed) in sides {
+            if let Packed::Blocks { data, .. } = packed {
+                align(&mut bytes, 64);
+                bytes.extend(data);
+            }
+        }
+        bytes
+    }
+
+    //Generated results by table position number, white first, for a table with white's
+    //king, a second white piece and black's king. Placements sharing a number must agree
+    fn results_by_index(pieces: [u8; 3]) -> [Vec<Option<Probe>>; 2] {
+        let layout = Layout::new(pieces.to_vec(), 0, true).expect("layout is valid");
+        let second_piece = if pieces[1] == 5 {ChessPiece::Queen} else {ChessPiece::Knight};
+        let mut results = [vec![None; layout.size() as usize], vec![None; layout.size() as usize]];
+        for (side_results, whose_turn) in results.iter_mut().zip([Side::White, Side::Black]) {
+            for (king, piece, other_king) in placements() {
+                let board = king_and_piece_board(second_piece, king, piece, other_king);
+                let probe = match tablebases().probe(&board, whose_turn) {
+                    Some(probe) => probe,
+                    None => continue
+                };
+                let squares = [king, piece, other_king].map(|(x, y)| ((7 - y) * 8 + x) as usize);
+                let index = layout.index(pieces.into_iter().zip(squares).collect()).expect("position is numbered") as usize;
+                assert!(side_results[index].is_none_or(|stored| stored == probe), "{:?} {:?}", squares, whose_turn);
+                side_results[index] = Some(probe);
+            }
+        }
+        results
+    }
+
+    fn placements() -> impl Iterator<Item = ((i8, i8), (i8, i8), (i8, i8))> {
+        let square = |index: i32| ((index % 8) as i8, (index / 8) as i8);
+        (0..64 * 64 * 64)
+            .map(move |index| (square(index / 4096), square(index / 64 % 64), square(index % 64)))
+            .filter(|(first, second, third)| first != second && first != third && second != third)
+    }
+
+    fn king_and_queen() -> &'static [Vec<Option<Probe>>; 2] {
+        static RESULTS: OnceLock<[Vec<Option<Probe>>; 2]> = OnceLock::new();
+        RESULTS.get_or_init(|| results_by_index(KQVK))
+    }
+
+    fn wdl_values(results: &[Option<Probe>]) -> Vec<u8> {
+        results.iter()
+            .map(|result| match result.map(|probe| probe.wdl) {
+                Some(Wdl::Loss) => 0,
+                Some(Wdl::Win) => 4,
+                _ => 2
+            })
+            .collect()
+    }
+
+    fn dtz_values(results: &[Option<Probe>]) -> Vec<u16> {
+        results.iter()
+            .map(|result| match result {
+                Some(probe) if probe.wdl != Wdl::Draw => probe.dtz.max(1) - 1,
+                _ => 0
+            })
+            .collect()
+    }
+
+    //KQvK with a DTZ file stored for white in plies, and KNvK, which only ever draws
+    fn write_plain_tables(directory: &TempTables) {
+        let results = king_and_queen();
+        let sides = [(0, pack(&wdl_values(&results[0]))), (0, pack(&wdl_values(&results[1])))];
+        directory.write("KQvK.rtbw", table_file(false, &KQVK, &sides, None));
+        let dtz: Vec<u8> = dtz_values(&results[0]).iter().map(|dtz| *dtz as u8).collect();
+        let sides = [(WIN_PLIES | LOSS_PLIES, pack(&dtz))];
+        directory.write("KQvK.rtbz", table_file(true, &KQVK, &sides, None));
+
+        let draws = vec![2; Layout::new(KNVK.to_vec(), 0, true).unwrap().size() as usize];
+        let sides = [(0, pack(&draws)), (0, pack(&draws))];
+        directory.write("KNvK.rtbw", table_file(false, &KNVK, &sides, None));
+    }
+
+    //Table files for one test, the directory goes when this is dropped, also when an assert
+    //fails. Files are only read once probed, so it has to outlive the tables opened from it
+    struct TempTables(PathBuf);
+
+    impl TempTables {
+        fn new(name: &str) -> TempTables {
+            let directory = std::env::temp_dir().join(format!("chess_minimax_syzygy_{}_{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&directory);
+            fs::create_dir_all(&directory).expect("directory is created");
+            TempTables(directory)
+        }
+
+        fn write(&self, name: &str, bytes: impl AsRef<[u8]>) {
+            fs::write(self.0.join(name), bytes).expect("file is written");
+        }
+
+        fn open(&self) -> io::Result<SyzygyTables> {
+            SyzygyTables::open(&self.0)
+        }
+    }
+
+    impl Drop for TempTables {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    //A spread of placements, rather than all of them, with either side to move
+    fn sample_positions() -> impl Iterator<Item = ((i8, i8), (i8, i8), (i8, i8))> {
+        placements().step_by(97)
+    }
+
+    #[test]
+    fn leading_groups_number_every_placement() {
+        let kings = &square_maps().kings;
+        assert_eq!(kings.iter().flatten().max(), Some(&(KING_PLACEMENTS as usize - 1)));
+        assert_eq!(Layout::new(KQVK.to_vec(), 0, true).unwrap().size(), UNIQUE_PLACEMENTS);
+        //Two rooks and no unique piece: the kings lead, the rooks take any two free squares
+        let layout = Layout::new(vec![6, 14, 4, 4], 0, false).unwrap();
+        assert_eq!(layout.group_len, [2, 2]);
+        assert_eq!(layout.size(), KING_PLACEMENTS * 62 * 61 / 2);
+    }
+
+    #[test]
+    fn written_tables_read_back() {
+        let directory = TempTables::new("plain");
+        write_plain_tables(&directory);
+        let syzygy = directory.open().expect("directory is read");
+        assert_eq!(syzygy.names(), ["KNvK", "KQvK"]);
+        assert_eq!(syzygy.max_pieces(), 3);
+
+        for (king, piece, other_king) in sample_positions() {
+            for second_piece in [ChessPiece::Queen, ChessPiece::Knight] {
+                let board = king_and_piece_board(second_piece, king, piece, other_king);
+                for whose_turn in [Side::White, Side::Black] {
+                    let expected = tablebases().probe(&board, whose_turn);
+                    if expected.is_none() {continue;}
+                    assert_eq!(syzygy.probe(&board, whose_turn), expected, "{:?} {:?} {:?}", king, piece, other_king);
+                    assert_eq!(syzygy.probe_wdl(&board, whose_turn), expected.map(|probe| probe.wdl));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mapped_dtz_stored_for_black_reads_back() {
+        let results = king_and_queen();
+        let directory = TempTables::new("mapped");
+        let sides = [(0, pack(&wdl_values(&results[0]))), (0, pack(&wdl_values(&results[1])))];
+        directory.write("KQvK.rtbw", table_file(false, &KQVK, &sides, None));
+
+        //Black only ever loses or draws, the losses' distances go in the map
+        let distances = dtz_values(&results[1]);
+        let mut losses: Vec<u16> = distances.clone();
+        losses.sort_unstable();
+        losses.dedup();
+        let stored: Vec<u8> = distances.iter().map(|dtz| losses.binary_search(dtz).unwrap() as u8).collect();
+        let map = [vec![], losses, vec![], vec![]];
+        let sides = [(STORED_SIDE | MAPPED | WIDE | WIN_PLIES | LOSS_PLIES, pack(&stored))];
+        directory.write("KQvK.rtbz", table_file(true, &KQVK, &sides, Some((&map, true))));
+        let syzygy = directory.open().expect("directory is read");
+
+        for (king, queen, other_king) in sample_positions() {
+            let board = king_and_queen_board(king, queen, other_king);
+            for whose_turn in [Side::White, Side::Black] {
+                let expected = tablebases().probe(&board, whose_turn);
+                if expected.is_none() {continue;}
+                assert_eq!(syzygy.probe(&board, whose_turn), expected, "{:?} {:?} {:?}", king, queen, other_king);
+            }
+        }
+    }
+
+    #[test]
+    fn black_with_the_queen_reads_the_table_with_colours_swapped() {
+        let directory = TempTables::new("swapped");
+        write_plain_tables(&directory);
+        let syzygy = directory.open().expect("directory is read");
+
+        for (king, queen, other_king) in sample_positions() {
+            let mirrored = |(x, y): (i8, i8)| (x, 7 - y);
+            let board = Board::from_pieces(&[
+                (ChessPiece::King, Side::Black, mirrored(king)),
+                (ChessPiece::Queen, Side::Black, mirrored(queen)),
+                (ChessPiece::King, Side::White, mirrored(other_king))
+            ]);
+            let original = king_and_queen_board(king, queen, other_king);
+            for whose_turn in [Side::White, Side::Black] {
+                let expected = tablebases().probe(&original, swap_turn(whose_turn));
+                if expected.is_none() {continue;}
+                assert_eq!(syzygy.probe(&board, whose_turn), expected, "{:?} {:?} {:?}", king, queen, other_king);
+            }
+        }
+    }
+
+    #[test]
+    fn tables_with_pawns_and_other_files_are_skipped() {
+        let directory = TempTables::new("skipped");
+        for name in ["KPvK.rtbw", "KQvK.tb", "notes.txt", "KQvK.rtbz", "KQQQQvK.rtbw"] {
+            directory.write(name, b"not a table");
+        }
+        let syzygy = directory.open().expect("directory is read");
+        assert!(syzygy.is_empty());
+        assert_eq!(syzygy.skipped_pawn_tables(), 1);
+    }
+
+    #[test]
+    fn broken_files_are_not_probed() {
+        let directory = TempTables::new("broken");
+        directory.write("KQvK.rtbw", [0x71, 0xE8, 0x23, 0x5D, 0, 0]);
+        let syzygy = directory.open().expect("directory is read");
+        assert_eq!(syzygy.len(), 1);
+        assert_eq!(syzygy.probe_wdl(&king_and_queen_board((4, 4), (7, 7), (0, 0)), Side::White), None);
+    }
+
+    #[test]
+    fn corrupt_blocks_are_not_probed() {
+        let results = king_and_queen();
+        let sides = [(0, pack(&wdl_values(&results[0]))), (0, pack(&wdl_values(&results[1])))];
+        let bytes = table_file(false, &KQVK, &sides, None);
+        let material = Material::from_name("KQvK").expect("name is an endgame");
+        let file = TableFile::read(bytes.clone(), false, &material).expect("table is valid");
+
+        //Every block white's sparse index points at lies past the blocks
+        let mut bad_index = bytes.clone();
+        let white = &file.sides[0];
+        for entry in 0..white.sparse_index_len {
+            let at = white.sparse_index + 6 * entry;
+            bad_index[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        }
+        //Black's blocks come last, the last one is cut in half
+        let truncated = bytes[..bytes.len() - (1 << BLOCK_BITS) / 2].to_vec();
+
+        for (name, corrupt, broken_side) in [("bad_index", bad_index, Side::White), ("truncated", truncated, Side::Black)] {
+            let directory = TempTables::new(name);
+            directory.write("KQvK.rtbw", corrupt);
+            let syzygy = directory.open().expect("directory is read");
+
+            let mut unreadable = 0;
+            for (king, queen, other_king) in sample_positions() {
+                let board = king_and_queen_board(king, queen, other_king);
+                let expected = tablebases().probe(&board, broken_side).map(|probe| probe.wdl);
+                if expected.is_none() {continue;}
+                //What can still be read is right, the rest is not guessed at
+                match syzygy.probe_wdl(&board, broken_side) {
+                    Some(wdl) => assert_eq!(Some(wdl), expected, "{} {:?} {:?} {:?}", name, king, queen, other_king),
+                    None => unreadable += 1
+                }
+            }
+            assert!(unreadable > 0, "{}", name);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs KQvK.rtbw and KQvK.rtbz from the Syzygy 3-4-5 set in src/chess/tablebase/testdata"]
+    fn real_king_and_queen_tables() {
+        let syzygy = SyzygyTables::open(Path::new(REAL_TABLES)).expect("test tables are in place");
+        assert_eq!(syzygy.names(), ["KQvK"]);
+
+        //Kb6, Qh7 against Ka8: mate on the next move
+        let mate_in_one = king_and_queen_board((1, 2), (7, 1), (0, 0));
+        assert_eq!(syzygy.probe(&mate_in_one, Side::White), Some(Probe { wdl: Wdl::Win, dtz: 1 }));
+        //Ka6, Qd7 against Ka8: Kb8 is the only move and Qb7 mates
+        let mated_in_two = king_and_queen_board((0, 2), (3, 1), (0, 0));
+        let probe = syzygy.probe(&mated_in_two, Side::Black).expect("position is covered");
+        assert_eq!(probe.wdl, Wdl::Loss);
+        //A file that stores moves rather than plies rounds the distance up to an odd one
+        assert!(probe.dtz == 2 || probe.dtz == 3, "{:?}", probe);
+        //Kd5 takes the undefended queen on d4
+        let hanging_queen = king_and_queen_board((7, 7), (3, 4), (3, 3));
+        assert_eq!(syzygy.probe_wdl(&hanging_queen, Side::Black), Some(Wdl::Draw));
+
+        for (king, queen, other_king) in sample_positions() {
+            let board = king_and_queen_board(king, queen, other_king);
+            for whose_turn in [Side::White, Side::Black] {
+                let expected = tablebases().probe(&board, whose_turn);
+                if expected.is_none() {continue;}
+                assert_eq!(syzygy.probe_wdl(&board, whose_turn), expected.map(|probe| probe.wdl), "{:?} {:?} {:?}", king, queen, other_king);
+            }
+        }
+    }
+
+    //Generated tables and Syzygy files alike
+    #[test]
+    fn root_move_mates_when_it_can() {
+        let directory = TempTables::new("root");
+        write_plain_tables(&directory);
+        let syzygy = directory.open().expect("directory is read");
+        let from_syzygy = Tablebases { syzygy, tables: HashMap::new() };
+
+        for tablebases in [tablebases().as_ref(), &from_syzygy] {
+            let mut board = king_and_queen_board((2, 1), (7, 5), (0, 0));
+            let (l_move, probe) = tablebases.best_root_move(&board, Side::White).expect("position is covered");
+            assert_eq!(probe, Probe { wdl: Wdl::Win, dtz: 1 });
+            board.make_move(&l_move);
+            assert!(board.is_in_check(Side::Black));
+            assert!(board.get_legal_moves_for_side(Side::Black).is_empty());
+        }
+    }
+}
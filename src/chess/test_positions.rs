@@ -0,0 +1,40 @@
+//Positions the tests of several modules share
+use crate::{Side, Board};
+use super::piece::ChessPiece;
+
+//Rook to (0, 0) mates, the pawns box the black king in
+pub fn back_rank_mate() -> Board {
+    Board::from_pieces(&[
+        (ChessPiece::King, Side::White, (3, 7)),
+        (ChessPiece::Rook, Side::White, (0, 7)),
+        (ChessPiece::King, Side::Black, (7, 0)),
+        (ChessPiece::Pawn, Side::Black, (6, 1)),
+        (ChessPiece::Pawn, Side::Black, (7, 1))
+    ])
+}
+
+//Both sides developed, with plenty of quiet moves and a few captures
+pub fn middlegame() -> Board {
+    Board::from_pieces(&[
+        (ChessPiece::King, Side::White, (6, 7)),
+        (ChessPiece::Queen, Side::White, (3, 7)),
+        (ChessPiece::Rook, Side::White, (0, 7)),
+        (ChessPiece::Knight, Side::White, (5, 5)),
+        (ChessPiece::Bishop, Side::White, (2, 5)),
+        (ChessPiece::Pawn, Side::White, (0, 6)),
+        (ChessPiece::Pawn, Side::White, (1, 6)),
+        (ChessPiece::Pawn, Side::White, (5, 6)),
+        (ChessPiece::Pawn, Side::White, (6, 6)),
+        (ChessPiece::Pawn, Side::White, (7, 6)),
+        (ChessPiece::King, Side::Black, (6, 0)),
+        (ChessPiece::Queen, Side::Black, (3, 0)),
+        (ChessPiece::Rook, Side::Black, (0, 0)),
+        (ChessPiece::Knight, Side::Black, (2, 2)),
+        (ChessPiece::Bishop, Side::Black, (5, 2)),
+        (ChessPiece::Pawn, Side::Black, (0, 1)),
+        (ChessPiece::Pawn, Side::Black, (1, 1)),
+        (ChessPiece::Pawn, Side::Black, (5, 1)),
+        (ChessPiece::Pawn, Side::Black, (6, 1)),
+        (ChessPiece::Pawn, Side::Black, (7, 1))
+    ])
+}
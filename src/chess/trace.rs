@@ -0,0 +1,533 @@
+use std::fmt::Write;
+
+use super::board::Move;
+
+/// What kind of search a traced node ran.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceKind {
+    Root,
+    Search,
+    Quiescence
+}
+
+/// Why a node stopped early or searched a move differently.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TraceEvent {
+    /// A move reached beta after `move_index` earlier moves, the rest were skipped.
+    BetaCutoff { move_index: usize },
+    TableCutoff,
+    TablebaseHit,
+    NullMoveCutoff,
+    /// The static evaluation stayed above beta by the reverse futility margin.
+    ReverseFutility,
+    /// Quiescence confirmed that the node fails low.
+    Razored,
+    FutilityPruned { l_move: Move },
+    LateMovePruned { l_move: Move },
+    /// The static evaluation already reached beta in quiescence.
+    StandPat,
+    /// Captures quiescence skipped because they lose material in the exchange.
+    LosingCapturesSkipped { count: usize },
+    Reduced { l_move: Move, reduction: i32 },
+    Extended { l_move: Move, plies: i32 },
+    /// A null-window or reduced search failed high and the move was searched again.
+    ReSearched { l_move: Move },
+    Checkmate,
+    Stalemate,
+    /// The position came up before and was scored as a draw.
+    Repetition
+}
+
+/// One call of the search. Scores, alpha and beta are from the point of view of the side to move.
+#[derive(Clone, Debug)]
+pub struct TraceNode {
+    /// Move leading here, `None` at the root and for the null move.
+    pub l_move: Option<Move>,
+    pub kind: TraceKind,
+    pub depth: i32,
+    pub alpha: i32,
+    pub beta: i32,
+    pub score: i32,
+    /// Nodes searched below this one, including those deeper than the trace goes.
+    pub nodes: u64,
+    pub events: Vec<TraceEvent>,
+    pub children: Vec<TraceNode>
+}
+
+/// Tree of the last completed iteration, one root per search of the root position:
+/// aspiration re-searches and every MultiPV line get their own.
+#[derive(Clone, Debug, Default)]
+pub struct SearchTrace {
+    /// Plies below the root that were recorded.
+    pub max_ply: usize,
+    pub iteration_depth: i32,
+    pub roots: Vec<TraceNode>
+}
+
+/// Records the tree while the search runs, nodes deeper than `max_ply` are only counted.
+pub struct TraceBuilder {
+    max_ply: usize,
+    //Nodes entered but not left yet, the innermost last
+    open: Vec<TraceNode>,
+    //Levels entered below max_ply
+    hidden: usize,
+    //Moves leading from the root to the current position, `None` for a null move
+    path: Vec<Option<Move>>,
+    current: SearchTrace,
+    completed: Option<SearchTrace>
+}
+
+impl TraceBuilder {
+    pub fn new(max_ply: usize) -> TraceBuilder {
+        TraceBuilder {
+            max_ply,
+            open: vec![],
+            hidden: 0,
+            path: vec![],
+            current: SearchTrace::default(),
+            completed: None
+        }
+    }
+
+    pub fn start_iteration(&mut self, depth: i32) {
+        self.open.clear();
+        self.hidden = 0;
+        self.path.clear();
+        self.current = SearchTrace { max_ply: self.max_ply, iteration_depth: depth, roots: vec![] };
+    }
+
+    pub fn finish_iteration(&mut self) {
+        self.completed = Some(std::mem::take(&mut self.current));
+    }
+
+    /// `None` passes the turn for a null move.
+    pub fn make_move(&mut self, l_move: Option<&Move>) {
+        self.path.push(l_move.cloned());
+    }
+
+    pub fn unmake_move(&mut self) {
+        self.path.pop();
+    }
+
+    pub fn enter(&mut self, kind: TraceKind, depth: i32, alpha: i32, beta: i32) {
+        if self.hidden > 0 || self.open.len() > self.max_ply {
+            self.hidden += 1;
+            return;
+        }
+        let l_move = self.path.last().cloned().flatten();
+        self.open.push(TraceNode { l_move, kind, depth, alpha, beta, score: 0, nodes: 1, events: vec![], children: vec![] });
+    }
+
+    pub fn leave(&mut self, score: i32) {
+        if self.hidden > 0 {
+            self.hidden -= 1;
+            if let Some(parent) = self.open.last_mut() {parent.nodes += 1;}
+            return;
+        }
+        let mut node = match self.open.pop() {
+            Some(node) => node,
+            None => return
+        };
+        node.score = score;
+        match self.open.last_mut() {
+            Some(parent) => {
+                parent.nodes += node.nodes;
+                parent.children.push(node);
+            },
+            None => self.current.roots.push(node)
+        }
+    }
+
+    /// Attaches the event to the innermost recorded node, events below the trace are dropped.
+    pub fn event(&mut self, event: TraceEvent) {
+        if self.hidden > 0 {return;}
+        if let Some(node) = self.open.last_mut() {node.events.push(event);}
+    }
+
+    pub fn finish(self) -> Option<SearchTrace> {
+        self.completed
+    }
+}
+
+fn move_json(l_move: &Option<Move>) -> String {
+    match l_move {
+        Some(l_move) => format!("{{\"from\": [{}, {}], \"to\": [{}, {}]}}", l_move.from.0, l_move.from.1, l_move.to.0, l_move.to.1),
+        None => "null".to_owned()
+    }
+}
+
+fn move_label(l_move: &Move) -> String {
+    format!("({}, {})->({}, {})", l_move.from.0, l_move.from.1, l_move.to.0, l_move.to.1)
+}
+
+fn kind_name(kind: TraceKind) -> &'static str {
+    match kind {
+        TraceKind::Root => "root",
+        TraceKind::Search => "search",
+        TraceKind::Quiescence => "quiescence"
+    }
+}
+
+fn event_json(event: &TraceEvent) -> String {
+    match event {
+        TraceEvent::BetaCutoff { move_index } => format!("{{\"type\": \"beta_cutoff\", \"move_index\": {}}}", move_index),
+        TraceEvent::TableCutoff => "{\"type\": \"table_cutoff\"}".to_owned(),
+        TraceEvent::TablebaseHit => "{\"type\": \"tablebase_hit\"}".to_owned(),
+        TraceEvent::NullMoveCutoff => "{\"type\": \"null_move_cutoff\"}".to_owned(),
+        TraceEvent::ReverseFutility => "{\"type\": \"reverse_futility\"}".to_owned(),
+        TraceEvent::Razored => "{\"type\": \"razored\"}".to_owned(),
+        TraceEvent::FutilityPruned { l_move } => format!("{{\"type\": \"futility_pruned\", \"move\": {}}}", move_json(&Some(l_move.clone()))),
+        TraceEvent::LateMovePruned { l_move } => format!("{{\"type\": \"late_move_pruned\", \"move\": {}}}", move_json(&Some(l_move.clone()))),
+        TraceEvent::StandPat => "{\"type\": \"stand_pat\"}".to_owned(),
+        TraceEvent::LosingCapturesSkipped { count } => format!("{{\"type\": \"losing_captures_skipped\", \"count\": {}}}", count),
+        TraceEvent::Reduced { l_move, reduction } => format!("{{\"type\": \"reduced\", \"move\": {}, \"reduction\": {}}}", move_json(&Some(l_move.clone())), reduction),
+        TraceEvent::Extended { l_move, plies } => format!("{{\"type\": \"extended\", \"move\": {}, \"plies\": {}}}", move_json(&Some(l_move.clone())), plies),
+        TraceEvent::ReSearched { l_move } => format!("{{\"type\": \"re_searched\", \"move\": {}}}", move_json(&Some(l_move.clone()))),
+        TraceEvent::Checkmate => "{\"type\": \"checkmate\"}".to_owned(),
+        TraceEvent::Stalemate => "{\"type\": \"stalemate\"}".to_owned(),
+        TraceEvent::Repetition => "{\"type\": \"repetition\"}".to_owned()
+    }
+}
+
+fn event_label(event: &TraceEvent) -> String {
+    match event {
+        TraceEvent::BetaCutoff { move_index } => format!("beta cutoff at move {}", move_index + 1),
+        TraceEvent::TableCutoff => "table cutoff".to_owned(),
+        TraceEvent::TablebaseHit => "tablebase hit".to_owned(),
+        TraceEvent::NullMoveCutoff => "null move cutoff".to_owned(),
+        TraceEvent::ReverseFutility => "reverse futility".to_owned(),
+        TraceEvent::Razored => "razored".to_owned(),
+        TraceEvent::FutilityPruned { l_move } => format!("{} futility pruned", move_label(l_move)),
+        TraceEvent::LateMovePruned { l_move } => format!("{} late move pruned", move_label(l_move)),
+        TraceEvent::StandPat => "stand pat".to_owned(),
+        TraceEvent::LosingCapturesSkipped { count } => format!("{} losing captures skipped", count),
+        TraceEvent::Reduced { l_move, reduction } => format!("{} reduced by {}", move_label(l_move), reduction),
+        TraceEvent::Extended { l_move, plies } => format!("{} extended by {}", move_label(l_move), plies),
+        TraceEvent::ReSearched { l_move } => format!("{} re-searched", move_label(l_move)),
+        TraceEvent::Checkmate => "checkmate".to_owned(),
+        TraceEvent::Stalemate => "stalemate".to_owned(),
+        TraceEvent::Repetition => "repetition".to_owned()
+    }
+}
+
+fn write_json_node(out: &mut String, node: &TraceNode, indent: usize) {
+    let pad = "  ".repeat(indent);
+    let events: Vec<String> = node.events.iter().map(event_json).collect();
+    let _ = write!(out, "{pad}{{\"move\": {}, \"kind\": \"{}\", \"depth\": {}, \"alpha\": {}, \"beta\": {}, \"score\": {}, \"nodes\": {}, \"events\": [{}], \"children\": [",
+        move_json(&node.l_move), kind_name(node.kind), node.depth, node.alpha, node.beta, node.score, node.nodes, events.join(", "));
+    if node.children.is_empty() {
+        out.push_str("]}");
+        return;
+    }
+    out.push('\n');
+    for (index, child) in node.children.iter().enumerate() {
+        write_json_node(out, child, indent + 1);
+        out.push_str(if index + 1 < node.children.len() {",\n"} else {"\n"});
+    }
+    let _ = write!(out, "{pad}]}}");
+}
+
+fn write_dot_node(out: &mut String, node: &TraceNode, parent: Option<usize>, next_id: &mut usize) {
+    let id = *next_id;
+    *next_id += 1;
+
+    let mut label = match (&node.l_move, node.kind) {
+        (_, TraceKind::Root) => "root".to_owned(),
+        (Some(l_move), _) => move_label(l_move),
+        (None, _) => "null move".to_owned()
+    };
+    let _ = write!(label, "\\n{} d={} [{}, {}]\\nscore {} nodes {}", kind_name(node.kind), node.depth, node.alpha, node.beta, node.score, node.nodes);
+    for event in &node.events {
+        let _ = write!(label, "\\n{}", event_label(event));
+    }
+    let color = if node.score >= node.beta {
+        "firebrick"
+    } else if node.score <= node.alpha {
+        "gray50"
+    } else {
+        "forestgreen"
+    };
+    let _ = writeln!(out, "  n{} [label=\"{}\", color={}];", id, label, color);
+    if let Some(parent) = parent {
+        let _ = writeln!(out, "  n{} -> n{};", parent, id);
+    }
+    for child in &node.children {
+        write_dot_node(out, child, Some(id), next_id);
+    }
+}
+
+impl SearchTrace {
+    pub fn to_json(&self) -> String {
+        let mut out = format!("{{\"max_ply\": {}, \"iteration_depth\": {}, \"roots\": [\n", self.max_ply, self.iteration_depth);
+        for (index, root) in self.roots.iter().enumerate() {
+            write_json_node(&mut out, root, 1);
+            out.push_str(if index + 1 < self.roots.len() {",\n"} else {"\n"});
+        }
+        out.push_str("]}\n");
+        out
+    }
+
+    /// Graphviz graph, nodes that failed high are red, failed low gray and exact green.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph search {\n  node [shape=box, fontname=monospace];\n");
+        let mut next_id = 0;
+        for root in &self.roots {
+            write_dot_node(&mut out, root, None, &mut next_id);
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Just enough of JSON to read back what to_json writes
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Number(f64),
+        Text(String),
+        Array(Vec<Json>),
+        Object(Vec<(String, Json)>)
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> &Json {
+            match self {
+                Json::Object(fields) => &fields.iter().find(|(name, _)| name == key).unwrap().1,
+                _ => panic!("{:?} is not an object", self)
+            }
+        }
+
+        fn items(&self) -> &[Json] {
+            match self {
+                Json::Array(items) => items,
+                _ => panic!("{:?} is not an array", self)
+            }
+        }
+    }
+
+    struct Parser<'a> {
+        text: &'a [u8],
+        at: usize
+    }
+
+    impl Parser<'_> {
+        fn skip_space(&mut self) {
+            while self.at < self.text.len() && self.text[self.at].is_ascii_whitespace() {self.at += 1;}
+        }
+
+        fn expect(&mut self, byte: u8) -> Result<(), String> {
+            self.skip_space();
+            if self.text.get(self.at) != Some(&byte) {
+                return Err(format!("expected '{}' at {}", byte as char, self.at));
+            }
+            self.at += 1;
+            Ok(())
+        }
+
+        fn peek(&mut self) -> Option<u8> {
+            self.skip_space();
+            self.text.get(self.at).copied()
+        }
+
+        fn string(&mut self) -> Result<String, String> {
+            self.expect(b'"')?;
+            let start = self.at;
+            while self.text.get(self.at).ok_or("unterminated string")? != &b'"' {self.at += 1;}
+            self.at += 1;
+            Ok(String::from_utf8(self.text[start..self.at - 1].to_vec()).unwrap())
+        }
+
+        //Items up to `close`, separated by commas
+        fn list<T>(&mut self, close: u8, mut item: impl FnMut(&mut Self) -> Result<T, String>) -> Result<Vec<T>, String> {
+            let mut items = vec![];
+            if self.peek() == Some(close) {
+                self.at += 1;
+                return Ok(items);
+            }
+            loop {
+                items.push(item(self)?);
+                if self.peek() == Some(b',') {
+                    self.at += 1;
+                } else {
+                    self.expect(close)?;
+                    return Ok(items);
+                }
+            }
+        }
+
+        fn value(&mut self) -> Result<Json, String> {
+            match self.peek() {
+                Some(b'{') => {
+                    self.at += 1;
+                    self.list(b'}', |parser| {
+                        let key = parser.string()?;
+                        parser.expect(b':')?;
+                        Ok((key, parser.value()?))
+                    }).map(Json::Object)
+                },
+                Some(b'[') => {
+                    self.at += 1;
+                    self.list(b']', Self::value).map(Json::Array)
+                },
+                Some(b'"') => self.string().map(Json::Text),
+                Some(b'n') if self.text[self.at..].starts_with(b"null") => {
+                    self.at += 4;
+                    Ok(Json::Null)
+                },
+                Some(byte) if byte == b'-' || byte.is_ascii_digit() => {
+                    let start = self.at;
+                    self.at += 1;
+                    while self.at < self.text.len() && self.text[self.at].is_ascii_digit() {self.at += 1;}
+                    let number = std::str::from_utf8(&self.text[start..self.at]).unwrap();
+                    number.parse().map(Json::Number).map_err(|_| format!("bad number {}", number))
+                },
+                _ => Err(format!("unexpected input at {}", self.at))
+            }
+        }
+    }
+
+    fn parse_json(text: &str) -> Result<Json, String> {
+        let mut parser = Parser { text: text.as_bytes(), at: 0 };
+        let value = parser.value()?;
+        match parser.peek() {
+            None => Ok(value),
+            Some(_) => Err(format!("trailing input at {}", parser.at))
+        }
+    }
+
+    fn a_move() -> Move {
+        Move { from: (4, 6), to: (4, 5) }
+    }
+
+    #[test]
+    fn nodes_past_max_ply_only_count_towards_their_parent() {
+        let mut trace = TraceBuilder::new(1);
+        trace.start_iteration(3);
+        trace.enter(TraceKind::Root, 3, -100, 100);
+        trace.make_move(Some(&a_move()));
+        trace.enter(TraceKind::Search, 2, -100, 100);
+        //Two plies below the root, one past max_ply
+        for _ in 0..2 {
+            trace.make_move(Some(&a_move()));
+            trace.enter(TraceKind::Search, 1, -100, 100);
+            trace.make_move(None);
+            trace.enter(TraceKind::Quiescence, 0, -100, 100);
+            trace.leave(5);
+            trace.unmake_move();
+            trace.leave(7);
+            trace.unmake_move();
+        }
+        trace.leave(-7);
+        trace.unmake_move();
+        trace.leave(7);
+        trace.finish_iteration();
+
+        let trace = trace.finish().unwrap();
+        assert_eq!(trace.iteration_depth, 3);
+        assert_eq!(trace.roots.len(), 1);
+        let root = &trace.roots[0];
+        assert_eq!(root.children.len(), 1);
+        let child = &root.children[0];
+        assert_eq!(child.l_move, Some(a_move()));
+        assert_eq!((child.score, child.depth), (-7, 2));
+        assert!(child.children.is_empty());
+        assert_eq!(child.nodes, 1 + 4);
+        assert_eq!(root.nodes, 1 + 5);
+    }
+
+    #[test]
+    fn events_past_max_ply_are_dropped() {
+        let mut trace = TraceBuilder::new(0);
+        trace.start_iteration(2);
+        trace.enter(TraceKind::Root, 2, -100, 100);
+        trace.make_move(Some(&a_move()));
+        trace.enter(TraceKind::Search, 1, -100, 100);
+        trace.event(TraceEvent::NullMoveCutoff);
+        trace.leave(-150);
+        trace.unmake_move();
+        trace.event(TraceEvent::BetaCutoff { move_index: 0 });
+        trace.leave(150);
+        trace.finish_iteration();
+
+        let trace = trace.finish().unwrap();
+        let root = &trace.roots[0];
+        assert!(root.children.is_empty());
+        assert_eq!(root.nodes, 2);
+        assert_eq!(root.events, vec![TraceEvent::BetaCutoff { move_index: 0 }]);
+    }
+
+    #[test]
+    fn an_unfinished_iteration_is_not_reported() {
+        let mut trace = TraceBuilder::new(2);
+        trace.start_iteration(1);
+        trace.enter(TraceKind::Root, 1, -100, 100);
+        assert!(trace.finish().is_none());
+    }
+
+    #[test]
+    fn json_output_parses_back() {
+        let leaf = |l_move, score| TraceNode {
+            l_move, kind: TraceKind::Quiescence, depth: 0, alpha: -20, beta: -19, score, nodes: 1,
+            events: vec![TraceEvent::StandPat], children: vec![]
+        };
+        let first_root = TraceNode {
+            l_move: None, kind: TraceKind::Root, depth: 1, alpha: -20, beta: 20, score: 25, nodes: 3,
+            events: vec![TraceEvent::Reduced { l_move: a_move(), reduction: 1 }, TraceEvent::BetaCutoff { move_index: 1 }],
+            children: vec![leaf(Some(a_move()), -25), leaf(None, -30)]
+        };
+        //Aspiration re-search of the same position, nothing below it recorded
+        let second_root = TraceNode {
+            l_move: None, kind: TraceKind::Root, depth: 1, alpha: -20, beta: 100, score: 25, nodes: 1,
+            events: vec![], children: vec![]
+        };
+        let trace = SearchTrace { max_ply: 1, iteration_depth: 1, roots: vec![first_root, second_root] };
+
+        let json = parse_json(&trace.to_json()).unwrap();
+        assert_eq!(json.get("max_ply"), &Json::Number(1.0));
+        let roots = json.get("roots").items();
+        assert_eq!(roots.len(), 2);
+
+        let children = roots[0].get("children").items();
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].get("move").get("to").items(), &[Json::Number(4.0), Json::Number(5.0)]);
+        assert_eq!(children[0].get("score"), &Json::Number(-25.0));
+        assert_eq!(children[1].get("move"), &Json::Null);
+        assert!(children[1].get("children").items().is_empty());
+        assert_eq!(children[1].get("events").items()[0].get("type"), &Json::Text("stand_pat".to_owned()));
+        let events = roots[0].get("events").items();
+        assert_eq!(events[0].get("reduction"), &Json::Number(1.0));
+        assert_eq!(events[1].get("move_index"), &Json::Number(1.0));
+
+        assert!(roots[1].get("children").items().is_empty());
+        assert!(roots[1].get("events").items().is_empty());
+        assert_eq!(roots[1].get("kind"), &Json::Text("root".to_owned()));
+
+        let empty = parse_json(&SearchTrace::default().to_json()).unwrap();
+        assert!(empty.get("roots").items().is_empty());
+    }
+
+    #[test]
+    fn dot_output_links_every_node_to_its_parent() {
+        let child = TraceNode {
+            l_move: Some(a_move()), kind: TraceKind::Search, depth: 0, alpha: -10, beta: 10, score: 10, nodes: 1,
+            events: vec![], children: vec![]
+        };
+        let root = TraceNode {
+            l_move: None, kind: TraceKind::Root, depth: 1, alpha: -10, beta: 10, score: -10, nodes: 2,
+            events: vec![TraceEvent::Checkmate], children: vec![child]
+        };
+        let dot = SearchTrace { max_ply: 1, iteration_depth: 1, roots: vec![root.clone(), root] }.to_dot();
+        assert!(dot.starts_with("digraph search {"));
+        assert!(dot.trim_end().ends_with('}'));
+        assert_eq!(dot.matches(" [label=").count(), 4);
+        assert!(dot.contains("  n0 -> n1;\n"));
+        assert!(dot.contains("  n2 -> n3;\n"));
+        assert!(!dot.contains("n1 -> n2"));
+        assert!(dot.contains("\\ncheckmate\""));
+        assert!(dot.contains("(4, 6)->(4, 5)"));
+    }
+}
@@ -0,0 +1,185 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use super::board::Move;
+
+const SLOT_SIZE: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Exact = 0,
+    Lower = 1,
+    Upper = 2
+}
+
+#[derive(Clone, Debug)]
+pub struct TableEntry {
+    pub best_move: Option<Move>,
+    pub score: i32,
+    pub depth: i32,
+    pub bound: Bound
+}
+
+//The key is stored xored with the data, so a slot torn by two threads writing at once
+//fails verification on probe instead of handing back a mix of both entries
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64
+}
+
+/// Hash table shared between search threads without locking.
+pub struct TranspositionTable {
+    slots: Vec<Slot>
+}
+
+fn pack_move(l_move: &Option<Move>) -> u64 {
+    match l_move {
+        Some(Move {from, to}) => {
+            let squares = [from.0, from.1, to.0, to.1];
+            squares.iter().fold(1, |packed, coordinate| (packed << 3) | *coordinate as u64)
+        },
+        None => 0
+    }
+}
+
+fn unpack_move(packed: u64) -> Option<Move> {
+    if packed & (1 << 12) == 0 {return None;}
+    let coordinate = |shift: u64| ((packed >> shift) & 0b111) as i8;
+    Some(Move {
+        from: (coordinate(9), coordinate(6)),
+        to: (coordinate(3), coordinate(0))
+    })
+}
+
+fn pack_entry(entry: &TableEntry) -> u64 {
+    let depth = entry.depth.clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+    (entry.score as u32 as u64)
+        | (depth as u8 as u64) << 32
+        | (entry.bound as u64) << 40
+        | pack_move(&entry.best_move) << 42
+}
+
+fn unpack_entry(data: u64) -> TableEntry {
+    let bound = match (data >> 40) & 0b11 {
+        0 => Bound::Exact,
+        1 => Bound::Lower,
+        _ => Bound::Upper
+    };
+    TableEntry {
+        best_move: unpack_move((data >> 42) & 0x1FFF),
+        score: data as u32 as i32,
+        depth: (data >> 32) as u8 as i8 as i32,
+        bound
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> TranspositionTable {
+        //Largest power of two that fits, so the index is a mask
+        let max_slots = size_mb.max(1) * 1024 * 1024 / SLOT_SIZE;
+        let slot_count = 1 << (usize::BITS - 1 - max_slots.leading_zeros());
+        let slots = (0..slot_count)
+            .map(|_| Slot {key: AtomicU64::new(0), data: AtomicU64::new(0)})
+            .collect();
+        TranspositionTable { slots }
+    }
+
+    /// Forgets every entry, for a new game.
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Permille of slots in use, sampled from the start of the table.
+    pub fn hashfull(&self) -> u32 {
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        let used = sample.iter()
+            .filter(|slot| slot.data.load(Ordering::Relaxed) != 0)
+            .count();
+        (used * 1000 / sample.len()) as u32
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TableEntry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        if data == 0 || slot.key.load(Ordering::Relaxed) ^ data != key {return None;}
+        Some(unpack_entry(data))
+    }
+
+    pub fn store(&self, key: u64, entry: TableEntry) {
+        //Keep deeper results for the same position
+        if let Some(existing) = self.probe(key) {
+            if existing.depth > entry.depth && entry.bound != Bound::Exact {return;}
+        }
+        let slot = self.slot(key);
+        let data = pack_entry(&entry);
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::score::MATE;
+
+    fn assert_round_trip(entry: TableEntry) {
+        let unpacked = unpack_entry(pack_entry(&entry));
+        assert_eq!(unpacked.best_move, entry.best_move);
+        assert_eq!(unpacked.score, entry.score);
+        assert_eq!(unpacked.depth, entry.depth);
+        assert_eq!(unpacked.bound, entry.bound);
+    }
+
+    fn entry(score: i32, bound: Bound, best_move: Option<Move>) -> TableEntry {
+        TableEntry { best_move, score, depth: 6, bound }
+    }
+
+    #[test]
+    fn scores_survive_packing() {
+        let l_move = Some(Move { from: (3, 6), to: (3, 5) });
+        for score in [0, 1, -1, 275, -1234, MATE - 3, -MATE + 5, i32::MAX, i32::MIN] {
+            assert_round_trip(entry(score, Bound::Exact, l_move.clone()));
+        }
+    }
+
+    #[test]
+    fn bounds_survive_packing() {
+        for bound in [Bound::Exact, Bound::Lower, Bound::Upper] {
+            assert_round_trip(entry(-40, bound, None));
+        }
+    }
+
+    #[test]
+    fn moves_survive_packing() {
+        assert_round_trip(entry(10, Bound::Lower, None));
+        assert_round_trip(entry(10, Bound::Lower, Some(Move { from: (7, 7), to: (0, 0) })));
+        assert_round_trip(entry(10, Bound::Lower, Some(Move { from: (0, 0), to: (7, 7) })));
+        assert_round_trip(entry(-MATE + 2, Bound::Upper, Some(Move { from: (7, 7), to: (7, 7) })));
+    }
+
+    #[test]
+    fn depth_is_clamped_to_a_byte() {
+        let deep = TableEntry { best_move: None, score: 0, depth: 300, bound: Bound::Exact };
+        assert_eq!(unpack_entry(pack_entry(&deep)).depth, i8::MAX as i32);
+        let quiescence = TableEntry { best_move: None, score: 0, depth: -3, bound: Bound::Exact };
+        assert_eq!(unpack_entry(pack_entry(&quiescence)).depth, -3);
+    }
+
+    #[test]
+    fn stored_entries_are_found_by_key() {
+        let table = TranspositionTable::new(1);
+        let key = 0x1234_5678_9ABC_DEF0;
+        table.store(key, entry(-MATE + 5, Bound::Upper, Some(Move { from: (7, 7), to: (6, 5) })));
+        let found = table.probe(key).expect("entry is stored");
+        assert_eq!(found.score, -MATE + 5);
+        assert_eq!(found.best_move, Some(Move { from: (7, 7), to: (6, 5) }));
+        //Same slot, different position
+        assert!(table.probe(key ^ (1 << 63)).is_none());
+    }
+}
@@ -0,0 +1,46 @@
+use super::piece::{ChessPiece, Side};
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    (state, z ^ (z >> 31))
+}
+
+//Keys have to be the same in every run, table entries and saved positions depend on them,
+//so they come from a fixed seed rather than the configurable engine seed
+const ZOBRIST_SEED: u64 = 0x5EED;
+
+//One key per (side, piece, square), 2 * 6 * 64
+const fn compute_piece_keys() -> [u64; 768] {
+    let mut keys: [u64; 768] = [0; 768];
+    let mut state: u64 = ZOBRIST_SEED;
+    let mut i = 0;
+
+    while i < 768 {
+        let (next_state, key) = splitmix64(state);
+        keys[i] = key;
+        state = next_state;
+        i += 1;
+    }
+    keys
+}
+const PIECE_KEYS: [u64; 768] = compute_piece_keys();
+pub const BLACK_TO_MOVE_KEY: u64 = splitmix64(0xB1AC).1;
+
+pub fn piece_key(piece: &ChessPiece, side: Side, square: (i8, i8)) -> u64 {
+    let side_index = match side {
+        Side::White => 0,
+        Side::Black => 1
+    };
+    let square_index = (square.0 + square.1 * 8) as usize;
+    PIECE_KEYS[(side_index * 6 + *piece as usize) * 64 + square_index]
+}
+
+pub fn side_key(whose_turn: Side) -> u64 {
+    match whose_turn {
+        Side::White => 0,
+        Side::Black => BLACK_TO_MOVE_KEY
+    }
+}
@@ -1,8 +1,14 @@
 mod chess;
 use chess::board::Board;
 use chess::board_view::BoardView;
-use chess::minimax::opponents_turn;
+use chess::difficulty::Difficulty;
+use chess::engine::{game_over_message, opponents_turn, BookEngine, Engine, EngineConfig, EngineKind};
+use chess::engine_worker::EngineWorker;
 use chess::piece::Side;
+use chess::polyglot::{BookChoice, OpeningBook};
+use chess::tablebase::{Tablebases, MAX_PIECES};
+use chess::trace::SearchTrace;
+use std::sync::Arc;
 use macroquad::prelude::*;
 
 fn window_conf() -> Conf {
@@ -15,6 +21,150 @@ fn window_conf() -> Conf {
     }
 }
 
+//Value following `flag` on the command line, empty when it is the last argument
+fn arg_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next()?;
+    Some(args.next().unwrap_or_default())
+}
+
+//`--engine <minimax|mcts|random|greedy>` picks the opponent, minimax by default
+fn engine_from_args() -> EngineKind {
+    let name = match arg_value("--engine") {
+        Some(name) => name,
+        None => return EngineKind::Minimax
+    };
+    EngineKind::from_name(&name).unwrap_or_else(|| {
+        println!("Unknown engine '{}', using minimax", name);
+        EngineKind::Minimax
+    })
+}
+
+//`--seed <n>` makes the run replayable, the caller draws a fresh seed otherwise
+fn seed_from_args() -> Option<u64> {
+    arg_value("--seed").and_then(|seed| {
+        let parsed = seed.parse().ok();
+        if parsed.is_none() {println!("Invalid seed '{}', drawing a new one", seed);}
+        parsed
+    })
+}
+
+//`--threads <n>` limits the minimax search to that many threads, it uses every core otherwise.
+//Replayable runs always search on one
+fn threads_from_args() -> Option<usize> {
+    let threads = arg_value("--threads")?;
+    let parsed = threads.parse().ok().filter(|threads| *threads > 0);
+    if parsed.is_none() {println!("Invalid thread count '{}', using every core", threads);}
+    parsed
+}
+
+//`--multipv <n>` searches and reports the best n root moves, each with its own line
+fn multi_pv_from_args() -> Option<usize> {
+    let lines = arg_value("--multipv")?;
+    let parsed = lines.parse().ok().filter(|lines| *lines > 0);
+    if parsed.is_none() {println!("Invalid MultiPV line count '{}', reporting the best line only", lines);}
+    parsed
+}
+
+//`--book <path>` plays from a Polyglot book first, `--book-choice best` sticks to
+//the highest weighted move instead of picking by weight
+fn with_book_from_args(engine: Box<dyn Engine>, seed: u64) -> Box<dyn Engine> {
+    let path = match arg_value("--book") {
+        Some(path) => path,
+        None => return engine
+    };
+    let choice = match arg_value("--book-choice").as_deref() {
+        Some("best") => BookChoice::Best,
+        _ => BookChoice::Weighted
+    };
+    match OpeningBook::open(std::path::Path::new(&path)) {
+        Ok(book) if book.is_empty() => {
+            println!("Opening book {} has no entries", path);
+            engine
+        },
+        Ok(book) => {
+            println!("Opening book {} with {} entries", path, book.len());
+            Box::new(BookEngine::new(book, choice, engine, seed))
+        },
+        Err(error) => {
+            println!("Could not read opening book {}: {}", path, error);
+            engine
+        }
+    }
+}
+
+//`--tablebases <dir>` probes the Syzygy tables in `dir` for endgames without pawns. Pawn
+//endings are only generated when `--tablebase-cache <dir>` names a directory for them,
+//which is never the Syzygy directory unless that is the one given
+fn tablebases_from_args() -> Option<Arc<Tablebases>> {
+    let directory = arg_value("--tablebases");
+    let cache = arg_value("--tablebase-cache").filter(|cache| !cache.is_empty());
+    if directory.is_none() && cache.is_none() {return None;}
+
+    let mut tablebases = Tablebases::default();
+    if let Some(directory) = directory {
+        tablebases = Tablebases::open(std::path::Path::new(&directory)).unwrap_or_else(|error| {
+            println!("Could not read Syzygy tables {}: {}", directory, error);
+            Tablebases::default()
+        });
+        let syzygy = tablebases.syzygy();
+        if syzygy.is_empty() {
+            println!("No Syzygy tables recognised in {}", directory);
+        } else {
+            println!("Syzygy tables {} with {} endgames without pawns", directory, syzygy.len());
+        }
+        if syzygy.skipped_pawn_tables() > 0 {
+            println!("Skipped {} Syzygy tables with pawns, pawns move differently here", syzygy.skipped_pawn_tables());
+        }
+    }
+
+    let syzygy_tables = tablebases.syzygy().len();
+    match cache {
+        Some(cache) => match tablebases.load_pawn_endings(std::path::Path::new(&cache), MAX_PIECES) {
+            Ok(generated) => println!("Pawn endings {} with {} endgames, {} generated now", cache, tablebases.len() - syzygy_tables, generated),
+            Err(error) => println!("Could not load pawn endings {}: {}", cache, error)
+        },
+        None => println!("Pawn endings are not probed, --tablebase-cache <dir> generates them")
+    }
+    (!tablebases.is_empty()).then(|| Arc::new(tablebases))
+}
+
+//`--trace <plies>` records that much of every minimax search tree
+fn trace_depth_from_args() -> Option<usize> {
+    let plies = arg_value("--trace")?;
+    let parsed = plies.parse().ok();
+    if parsed.is_none() {println!("Invalid trace depth '{}', not tracing", plies);}
+    parsed
+}
+
+//Written to the working directory, one pair of files per engine move
+fn export_trace(trace: &SearchTrace, move_number: u32) {
+    for (extension, contents) in [("json", trace.to_json()), ("dot", trace.to_dot())] {
+        let path = format!("trace_{}.{}", move_number, extension);
+        match std::fs::write(&path, contents) {
+            Ok(()) => println!("Search trace written to {}", path),
+            Err(error) => println!("Could not write search trace {}: {}", path, error)
+        }
+    }
+}
+
+//`--contempt <n>` fixes how much the engine avoids draws, a pawn is 50. Without it the
+//engine judges the opponent by their moves
+fn contempt_from_args() -> Option<i32> {
+    let contempt = arg_value("--contempt")?;
+    let parsed = contempt.parse().ok();
+    if parsed.is_none() {println!("Invalid contempt '{}', estimating it instead", contempt);}
+    parsed
+}
+
+//Number keys pick the level, 1 is the weakest
+fn difficulty_from_keys() -> Option<Difficulty> {
+    const LEVEL_KEYS: [KeyCode; 5] = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5];
+    LEVEL_KEYS.iter()
+        .position(|key| is_key_pressed(*key))
+        .map(|level| Difficulty::ALL[level])
+}
+
 fn swap_turn(whose_turn: Side) -> Side {
     match whose_turn {
         Side::White => Side::Black,
@@ -29,12 +179,36 @@ async fn main() -> Result<(), FontError> {
     let mut current_resolution = (screen_width(), screen_height());
     let mut chess_board = Board::new(current_resolution.0, current_resolution.1).await;
     let mut board_view = BoardView::new(chess_board.text_spacing, chess_board.text_params);
+    //Searches on several threads, against the clock or on the player's time end at
+    //different points from run to run, so a given seed only replays without them
+    let replayable = seed_from_args();
+    let seed = replayable.unwrap_or_else(::rand::random);
+    match replayable {
+        Some(_) => println!("Engine seed: {}, replayable: one search thread, no time limits, no pondering", seed),
+        None => println!("Engine seed: {}, the run only replays exactly when started with --seed", seed)
+    }
+    let config = EngineConfig {
+        seed,
+        tablebases: tablebases_from_args(),
+        trace_depth: trace_depth_from_args(),
+        threads: if replayable.is_some() {Some(1)} else {threads_from_args()},
+        multi_pv: multi_pv_from_args()
+    };
+    let base_engine = engine_from_args().create(config);
+    let mut engine = EngineWorker::spawn(with_book_from_args(base_engine, seed));
+    engine.set_time_limits(replayable.is_none());
+    //`--ponder` keeps the engine searching while the player thinks
+    engine.set_pondering(replayable.is_none() && std::env::args().any(|arg| arg == "--ponder"));
+    engine.set_contempt(contempt_from_args());
 
     let background_color = Color::from_rgba(96, 96, 96, 255);
 
     let player_side = Side::White;
     //-1 or 1
     let mut whose_turn: Side = Side::White;
+    let mut engine_moves: u32 = 0;
+    //Set once the engine has no move left, nothing is played after that
+    let mut game_over: Option<String> = None;
 
     loop {
         let screen_width = screen_width();
@@ -48,7 +222,8 @@ async fn main() -> Result<(), FontError> {
 
         chess_board.draw();
 
-        if is_mouse_button_pressed(MouseButton::Left) {
+        //Board stays on screen while the engine thinks, but only the player's pieces can be picked up
+        if is_mouse_button_pressed(MouseButton::Left) && whose_turn == player_side {
             let piece_pickup = BoardView::was_piece_hit(chess_board.get_board_state(), &whose_turn);
             if let Some(piece_pickup) = piece_pickup {
                 board_view.pick_up_piece(piece_pickup);
@@ -61,10 +236,36 @@ async fn main() -> Result<(), FontError> {
         }
 
 
-        if whose_turn == Side::Black {
-            //Clone board before
-            let simulated_board = chess_board.clone();
-            whose_turn = opponents_turn(&mut chess_board, simulated_board, whose_turn);
+        if let Some(difficulty) = difficulty_from_keys() {
+            engine.set_difficulty(difficulty);
+        }
+        board_view.draw_difficulty(engine.difficulty());
+
+        if engine.is_thinking() {
+            board_view.draw_thinking_indicator(engine.name(), engine.info());
+            if is_key_pressed(KeyCode::Escape) {
+                engine.abort();
+            }
+            if let Some(result) = engine.try_receive() {
+                engine_moves += 1;
+                if let Some(trace) = result.as_ref().and_then(|result| result.trace.as_ref()) {
+                    export_trace(trace, engine_moves);
+                }
+                match opponents_turn(&mut chess_board, result, whose_turn) {
+                    Some(next_turn) => {
+                        whose_turn = next_turn;
+                        engine.ponder(&chess_board, whose_turn);
+                    },
+                    None => game_over = Some(game_over_message(&chess_board, whose_turn))
+                }
+            }
+        } else if let Some(message) = &game_over {
+            board_view.draw_game_over(message);
+        } else if whose_turn == Side::Black {
+            //Engine searches its own copy of the board
+            engine.start_thinking(chess_board.clone(), whose_turn);
+        } else if engine.is_pondering() {
+            board_view.draw_pondering_indicator(engine.name());
         }
         //swap_turn
         next_frame().await